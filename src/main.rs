@@ -1,13 +1,11 @@
 use clap::Parser;
 
+mod ocr;
 mod output;
 mod translate;
 mod utils;
 mod whisper;
 
-use genai::adapter::AdapterKind;
-use genai::resolver::{AuthData, Endpoint, ServiceTargetResolver};
-use genai::{Client, ModelIden, ServiceTarget};
 use output::OutputSubtitles;
 use tempfile::TempDir;
 
@@ -15,16 +13,26 @@ use tempfile::TempDir;
 #[command(version, about, long_about = None)]
 struct Args {
     /// Path to the input video
-    #[arg(short, long)]
-    input_video_path: String,
+    /// (default: None, required unless subtitle source is file)
+    /// (example: "one.webm")
+    #[arg(short, long, default_value = None)]
+    input_video_path: Option<String>,
 
     /// Which language to translate from
     /// (default: "ja")
-    /// (possible values: "en", "es", "fr", "de", "it", "ja", "ko", "pt", "ru", "zh")
+    /// (possible values: "auto", "en", "es", "fr", "de", "it", "ja", "ko", "pt", "ru", "zh")
     /// (example: "ja")
+    /// (long_about: "Language of the video, \"auto\" detects the spoken language with whisper if subtitle source is audio, or lets the translator detect it otherwise")
     #[arg(long, default_value = "ja")]
     source_language: String,
 
+    /// Language detection duration (if source language is auto)
+    /// (default: 30)
+    /// (example: 60)
+    /// (long_about: "Duration in seconds of the audio at the start used to detect the spoken language, if subtitle source is audio")
+    #[arg(long, default_value = "30")]
+    language_detection_duration: f32,
+
     /// Which language to translate to
     /// (default: "en")
     /// (possible values: "en", "es", "fr", "de", "it", "ja", "ko", "pt", "ru", "zh")
@@ -32,22 +40,102 @@ struct Args {
     #[arg(long, default_value = "en")]
     target_language: String,
 
-    /// Video start time (not used yet)
+    /// Video start time
+    /// (default: "0")
+    /// (example: "90.5", "00:01:30.500")
+    /// (long_about: "Start time of the part to subtitle, in seconds or HH:MM:SS.mmm")
     #[arg(long, default_value = "0")]
-    start_time: usize,
+    start_time: String,
 
-    /// Video end time (not used yet)
+    /// Video end time
+    /// (default: "0", until the end of the video)
+    /// (example: "390.5", "00:06:30.500")
+    /// (long_about: "End time of the part to subtitle, in seconds or HH:MM:SS.mmm")
     #[arg(long, default_value = "0")]
-    end_time: usize,
+    end_time: String,
+
+    /// Trim the video to the start and end time (if container)
+    /// (default: false)
+    /// (long_about: "Only keep the part between start and end time in the output video, if subtitle backend is container")
+    #[arg(long)]
+    trim_video: bool,
 
     /// Subtitle source
     /// (default: "audio")
-    /// (possible values: "audio", "container", "ocr")
+    /// (possible values: "audio", "container", "ocr", "file")
     /// (example: "audio")
     /// (long_about: "Subtitle source to use")
     #[arg(long, default_value = "audio")]
     subtitle_source: String,
 
+    /// Input subtitle path (if file)
+    /// (default: None)
+    /// (example: "one.ja.srt")
+    /// (long_about: "Path to the SRT, WebVTT or ASS file to translate, if subtitle source is file")
+    #[arg(long, default_value = None)]
+    input_subtitle_path: Option<String>,
+
+    /// Input subtitle encoding (if file)
+    /// (default: None, detected from the BOM or the source language)
+    /// (example: "shift_jis", "gbk")
+    /// (long_about: "Text encoding of the input subtitle file, if subtitle source is file")
+    #[arg(long, default_value = None)]
+    input_subtitle_encoding: Option<String>,
+
+    /// Audio track index (if audio)
+    /// (default: None)
+    /// (example: 1)
+    /// (long_about: "Stream index of the audio track to transcribe, if subtitle source is audio")
+    #[arg(long, default_value = None)]
+    audio_track: Option<usize>,
+
+    /// Audio track language (if audio)
+    /// (default: None)
+    /// (example: "jpn")
+    /// (long_about: "Language tag of the audio track to transcribe, if subtitle source is audio")
+    #[arg(long, default_value = None)]
+    audio_language: Option<String>,
+
+    /// Subtitle track index (if container)
+    /// (default: None)
+    /// (example: 2)
+    /// (long_about: "Stream index of the subtitle track to extract, if subtitle source is container")
+    #[arg(long, default_value = None)]
+    subtitle_track: Option<usize>,
+
+    /// Subtitle track language (if container)
+    /// (default: None)
+    /// (example: "jpn")
+    /// (long_about: "Language tag of the subtitle track to extract, if subtitle source is container")
+    #[arg(long, default_value = None)]
+    subtitle_language: Option<String>,
+
+    /// OCR subtitle region (if ocr)
+    /// (default: "0,0.75,1,0.25")
+    /// (example: "0,0.8,1,0.2")
+    /// (long_about: "Region of the frame to OCR as x,y,width,height in fractions of the frame size, if subtitle source is ocr")
+    #[arg(long, default_value = "0,0.75,1,0.25")]
+    ocr_region: String,
+
+    /// OCR sample interval in seconds (if ocr)
+    /// (default: 0.5)
+    /// (long_about: "Interval between two sampled frames in seconds, if subtitle source is ocr")
+    #[arg(long, default_value = "0.5")]
+    ocr_interval: f32,
+
+    /// OCR change threshold (if ocr)
+    /// (default: 0.2)
+    /// (long_about: "Ratio of changed text pixels to consider the subtitle changed, if subtitle source is ocr")
+    #[arg(long, default_value = "0.2")]
+    ocr_change_threshold: f32,
+
+    /// OCR language (if ocr)
+    /// (default: None, derived from the source language)
+    /// (example: "jpn", "chi_sim")
+    /// (long_about: "Tesseract language to use, if subtitle source is ocr")
+    #[arg(long, default_value = None)]
+    ocr_language: Option<String>,
+
     /// ggml model path
     /// (default: "ggml-tiny.bin")
     /// (example: "ggml-tiny.bin", ggml-small.bin")
@@ -55,6 +143,139 @@ struct Args {
     #[arg(long, default_value = "ggml-tiny.bin")]
     ggml_model_path: String,
 
+    /// Voice activity detection (if audio)
+    /// (default: false)
+    /// (example: true)
+    /// (long_about: "Only transcribe the speech regions of the audio, avoiding hallucinations over silence and music")
+    #[arg(long)]
+    vad: bool,
+
+    /// VAD energy threshold (if vad)
+    /// (default: -40.0)
+    /// (example: -35.0)
+    /// (long_about: "Minimum RMS level in dBFS of a speech frame")
+    #[arg(long, default_value = "-40.0", allow_negative_numbers = true)]
+    vad_energy_threshold: f32,
+
+    /// VAD zero crossing rate (if vad)
+    /// (default: 0.35)
+    /// (example: 0.3)
+    /// (long_about: "Maximum ratio of sign changes between samples of a speech frame, noisier frames are ignored")
+    #[arg(long, default_value = "0.35")]
+    vad_max_zero_crossing_rate: f32,
+
+    /// VAD minimum speech duration (if vad)
+    /// (default: 0.25)
+    /// (example: 0.5)
+    /// (long_about: "Shorter speech regions in seconds are dropped")
+    #[arg(long, default_value = "0.25")]
+    vad_min_speech_duration: f32,
+
+    /// VAD minimum silence duration (if vad)
+    /// (default: 0.5)
+    /// (example: 1.0)
+    /// (long_about: "Shorter pauses in seconds do not split a speech region")
+    #[arg(long, default_value = "0.5")]
+    vad_min_silence_duration: f32,
+
+    /// Whisper beam size (if audio)
+    /// (default: 0)
+    /// (example: 5)
+    /// (long_about: "Number of beams of the beam search decoding, greedy decoding if 0 or 1 (slower but usually more accurate with beams)")
+    #[arg(long, default_value = "0")]
+    whisper_beam_size: usize,
+
+    /// Whisper temperature (if audio)
+    /// (default: 0.0)
+    /// (example: 0.2)
+    /// (long_about: "Initial sampling temperature of the decoding")
+    #[arg(long, default_value = "0.0")]
+    whisper_temperature: f32,
+
+    /// Whisper temperature increment (if audio)
+    /// (default: 0.2)
+    /// (example: 0.4)
+    /// (long_about: "Temperature increase to retry a failed (repetitive or unlikely) decoding with, 0 disables the fallback")
+    #[arg(long, default_value = "0.2")]
+    whisper_temperature_inc: f32,
+
+    /// Whisper threads (if audio)
+    /// (default: None)
+    /// (example: 8)
+    /// (long_about: "CPU threads used by a whisper job, chosen by whisper.cpp (or shared between the jobs) if not specified")
+    #[arg(long, default_value = None)]
+    whisper_threads: Option<usize>,
+
+    /// Whisper initial prompt (if audio)
+    /// (default: None)
+    /// (example: "Episode of Jimaku Gumi, with Kaito and Sakura.")
+    /// (long_about: "Text given to whisper as previous context, to prime the names, terminology and style of the transcription")
+    #[arg(long, default_value = None)]
+    whisper_initial_prompt: Option<String>,
+
+    /// Whisper no context (if audio)
+    /// (default: true)
+    /// (example: false)
+    /// (long_about: "Do not use the text of the previous segments as context, which avoids repeating the same text over and over")
+    #[arg(long, default_value = "true", action = clap::ArgAction::Set)]
+    whisper_no_context: bool,
+
+    /// Whisper suppress blank (if audio)
+    /// (default: true)
+    /// (example: false)
+    /// (long_about: "Suppress blank outputs at the beginning of the segments")
+    #[arg(long, default_value = "true", action = clap::ArgAction::Set)]
+    whisper_suppress_blank: bool,
+
+    /// Whisper suppress non-speech tokens (if audio)
+    /// (default: false)
+    /// (example: true)
+    /// (long_about: "Suppress non-speech tokens like music notes and speaker annotations")
+    #[arg(long)]
+    whisper_suppress_non_speech_tokens: bool,
+
+    /// Whisper max segment length (if audio)
+    /// (default: 0)
+    /// (example: 42)
+    /// (long_about: "Maximum length of a segment in characters, segments are split on words; 0 for no limit")
+    #[arg(long, default_value = "0")]
+    whisper_max_len: usize,
+
+    /// Whisper output filter (if audio)
+    /// (default: false)
+    /// (example: true)
+    /// (long_about: "Drop the known hallucinations, repeated lines and unlikely segments of the transcription, and cut the repeated words")
+    #[arg(long)]
+    whisper_filter: bool,
+
+    /// Whisper hallucinations file path (if whisper filter)
+    /// (default: None)
+    /// (example: "hallucinations.txt")
+    /// (long_about: "File with one phrase per line to drop from the transcription, added to the known ones of the source language")
+    #[arg(long, default_value = None)]
+    whisper_hallucinations_path: Option<String>,
+
+    /// Whisper minimum probability (if whisper filter)
+    /// (default: 0.3)
+    /// (example: 0.5)
+    /// (long_about: "Segments with a lower average word probability are dropped")
+    #[arg(long, default_value = "0.3")]
+    whisper_min_probability: f32,
+
+    /// Whisper jobs (if audio)
+    /// (default: 1)
+    /// (example: 4)
+    /// (long_about: "Number of chunks of the audio (or speech regions if vad) transcribed in parallel, the CPU threads are shared between the jobs")
+    #[arg(long, default_value = "1")]
+    whisper_jobs: usize,
+
+    /// Whisper chunk duration (if whisper jobs > 1)
+    /// (default: 300)
+    /// (example: 120)
+    /// (long_about: "Target duration in seconds of the chunks transcribed in parallel, chunks are cut at the quietest point before")
+    #[arg(long, default_value = "300")]
+    whisper_chunk_duration: f32,
+
     /// Only extract the audio
     /// (default: false)
     /// (long_about: "Only extract the audio, if subtitle source is audio, but do not transcribe (Debug purpose)")
@@ -82,9 +303,24 @@ struct Args {
     #[arg(long)]
     only_translate: bool,
 
+    /// Re-segmentation profile
+    /// (default: None)
+    /// (possible values: "netflix", "bbc", "custom")
+    /// (example: "netflix")
+    /// (long_about: "Split, merge, re-time and break the lines of the subtitles to follow the readability limits of a profile")
+    #[arg(long, default_value = None)]
+    resegment_profile: Option<String>,
+
+    /// Re-segmentation overrides (if re-segmentation profile)
+    /// (default: "")
+    /// (example: "max_chars_per_line=32,max_chars_per_second=15")
+    /// (long_about: "Overrides of the profile fields: max_chars_per_line, max_lines, max_chars_per_second, min_duration, max_duration and min_gap")
+    #[arg(long, default_value = "")]
+    resegment_overrides: String,
+
     /// Subtitle backend
     /// (default: "srt")
-    /// (possible values: "srt", "container", "embedded")
+    /// (possible values: "srt", "vtt", "ass", "container", "embedded")
     /// (example: "srt")
     /// (long_about: "Subtitle backend to use")
     #[arg(short, long, default_value = "srt")]
@@ -93,10 +329,112 @@ struct Args {
     /// Subtitle output path
     /// (default: "None")
     /// (example: "output.srt")
-    /// (long_about: "Subtitle output path (if srt) or video output path (if container or embedded)")
+    /// (long_about: "Subtitle output path (if srt, vtt or ass) or video output path (if container or embedded)")
     #[arg(long, default_value = None)]
     subtitle_output_path: Option<String>,
 
+    /// Bilingual subtitles
+    /// (default: false)
+    /// (example: true)
+    /// (long_about: "Keep the original text with the translation, stacked above it if subtitle backend is srt or vtt, with the top `Original` style if ass or embedded, or as a second subtitle track if container")
+    #[arg(long)]
+    bilingual: bool,
+
+    /// Subtitle track title (if container)
+    /// (default: None)
+    /// (example: "English (AI)")
+    /// (long_about: "Title of the translated subtitle track shown by players, if subtitle backend is container")
+    #[arg(long, default_value = None)]
+    container_track_title: Option<String>,
+
+    /// Forced subtitle track (if container)
+    /// (default: false)
+    /// (long_about: "Flag the translated subtitle track as forced, if subtitle backend is container")
+    #[arg(long)]
+    container_track_forced: bool,
+
+    /// Subtitle codec (if container)
+    /// (default: None, chosen by the output container)
+    /// (possible values: "srt", "ass", "webvtt", "mov_text")
+    /// (example: "ass")
+    /// (long_about: "Codec of the muxed subtitle tracks, if subtitle backend is container; srt (or ass) for mkv, webvtt for webm and mov_text for mp4 and mov by default")
+    #[arg(long, default_value = None)]
+    container_subtitle_codec: Option<String>,
+
+    /// Replace the input video (if container or embedded)
+    /// (default: false)
+    /// (long_about: "Write the subtitles into the input video itself, verified before it is atomically replaced, if subtitle backend is container or embedded and no other subtitle output path is given")
+    #[arg(long)]
+    in_place: bool,
+
+    /// Back up the input video (if in place)
+    /// (default: false)
+    /// (long_about: "Keep the input video as `<input>.bak` before it is replaced, if muxing in place")
+    #[arg(long)]
+    in_place_backup: bool,
+
+    /// WebVTT cue settings (if vtt)
+    /// (default: "")
+    /// (example: "line:85% align:center")
+    /// (long_about: "Cue settings (position, line, align, size) applied to every cue, if subtitle backend is vtt")
+    #[arg(long, default_value = "")]
+    vtt_cue_settings: String,
+
+    /// WebVTT style file path (if vtt)
+    /// (default: None)
+    /// (example: "style.css")
+    /// (long_about: "CSS file written as a STYLE block, if subtitle backend is vtt")
+    #[arg(long, default_value = None)]
+    vtt_style_path: Option<String>,
+
+    /// WebVTT note (if vtt)
+    /// (default: None)
+    /// (example: "Translated by DeepL")
+    /// (long_about: "Comment written as a NOTE block, if subtitle backend is vtt")
+    #[arg(long, default_value = None)]
+    vtt_note: Option<String>,
+
+    /// ASS style file path (if ass or embedded)
+    /// (default: None)
+    /// (example: "styles.ass")
+    /// (long_about: "File with a [V4+ Styles] section (e.g. exported from Aegisub), the first style is the default one and the others render the lines of an ASS source with the same style name, if subtitle backend is ass or embedded")
+    #[arg(long, default_value = None)]
+    ass_style_path: Option<String>,
+
+    /// ASS default style overrides (if ass or embedded)
+    /// (default: "")
+    /// (example: "Fontname=Noto Sans CJK JP,Fontsize=60,MarginV=40")
+    /// (long_about: "Overrides of the default style fields, if subtitle backend is ass or embedded")
+    #[arg(long, default_value = "")]
+    ass_style: String,
+
+    /// ASS karaoke (if ass)
+    /// (default: false)
+    /// (example: true)
+    /// (long_about: "Highlight the words as they are spoken with karaoke tags, if subtitle backend is ass and the subtitles are transcribed but not translated")
+    #[arg(long)]
+    ass_karaoke: bool,
+
+    /// Video encoder (if embedded)
+    /// (default: "libx264")
+    /// (example: "libx265")
+    /// (long_about: "FFmpeg encoder used to re-encode the video, if subtitle backend is embedded")
+    #[arg(long, default_value = "libx264")]
+    video_codec: String,
+
+    /// Video CRF (if embedded)
+    /// (default: 23)
+    /// (long_about: "Constant rate factor of the video encoder, if subtitle backend is embedded")
+    #[arg(long, default_value = "23")]
+    video_crf: u32,
+
+    /// Video preset (if embedded)
+    /// (default: "medium")
+    /// (example: "veryslow")
+    /// (long_about: "Preset of the video encoder, if subtitle backend is embedded")
+    #[arg(long, default_value = "medium")]
+    video_preset: String,
+
     /// Translator backend
     /// (default: "deepl")
     /// (possible values: "deepl", "google", "llm", "whisper")
@@ -125,27 +463,128 @@ struct Args {
     /// (long_about: "Prompt (if using llm for translation)")
     #[arg(long, default_value = "")]
     llm_prompt: String,
+
+    /// Window size (if llm)
+    /// (default: 0)
+    /// (example: 20)
+    /// (long_about: "Number of lines translated together in one request, answered as JSON; 0 translates line by line (if using llm for translation)")
+    #[arg(long, default_value = "0")]
+    llm_window_size: usize,
+
+    /// Context size (if llm)
+    /// (default: 5)
+    /// (example: 5)
+    /// (long_about: "Number of preceding lines sent with their translation as context of a window (if using llm for translation)")
+    #[arg(long, default_value = "5")]
+    llm_context_size: usize,
 }
 
 fn main() {
     let args = Args::parse();
-    let input_video_path = args.input_video_path.as_str();
-    let source_language = args.source_language;
+    // Only the file subtitle source and the subtitle file backends work without a video
+    let needs_video = args.subtitle_source != "file"
+        || args.only_extract_audio
+        || (!args.only_transcript
+            && !args.only_translate
+            && matches!(args.subtitle_backend.as_str(), "container" | "embedded"));
+    // Outputs are named after the input subtitle file when there is no video
+    let input_video_path = match (&args.input_video_path, &args.input_subtitle_path) {
+        (Some(path), _) => path.clone(),
+        (None, Some(path)) if !needs_video => path.clone(),
+        _ => {
+            println!("The input video path is required, use --input-video-path");
+            return;
+        }
+    };
+    let input_video_path = input_video_path.as_str();
+    // The video backends never overwrite the input video unless asked
+    if matches!(args.subtitle_backend.as_str(), "container" | "embedded")
+        && !args.only_transcript
+        && !args.only_translate
+        && !args.in_place
+        && args
+            .subtitle_output_path
+            .as_deref()
+            .map_or(args.subtitle_backend == "container", |path| {
+                output::ffmpeg_subtitle::is_same_file(path, input_video_path)
+            })
+    {
+        println!("Refusing to overwrite the input video, use --subtitle-output-path or --in-place");
+        return;
+    }
+    let mut source_language = args.source_language;
     let target_language = args.target_language;
+    let time_range = match utils::time::TimeRange::parse(&args.start_time, &args.end_time) {
+        Ok(time_range) => time_range,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let readability_profile = match &args.resegment_profile {
+        Some(name) => {
+            match utils::resegment::ReadabilityProfile::from_name(name).and_then(|mut profile| {
+                profile.apply_overrides(&args.resegment_overrides)?;
+                Ok(profile)
+            }) {
+                Ok(profile) => Some(profile),
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let translator_config = translate::TranslatorConfig {
+        llm_model_name: args.llm_model_name.clone(),
+        llm_api_base: args.llm_api_base.clone(),
+        llm_prompt: args.llm_prompt.clone(),
+        llm_window_size: args.llm_window_size,
+        llm_context_size: args.llm_context_size,
+    };
+    let translator = match translate::TranslatorRegistry::default()
+        .create(&args.translator_backend, &translator_config)
+    {
+        Ok(translator) => translator,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
 
     println!("Hello, AI no jimaku gumi!");
 
+    let audio_selector = if let Some(index) = args.audio_track {
+        utils::ffmpeg_stream::StreamSelector::Index(index)
+    } else if let Some(language) = args.audio_language.clone() {
+        utils::ffmpeg_stream::StreamSelector::Language(language)
+    } else {
+        utils::ffmpeg_stream::StreamSelector::Best
+    };
+
     let tmp_dir = TempDir::new().unwrap();
     let tmp_path = tmp_dir.path().join("audio.wav");
     let tmp_path_str = tmp_path.as_os_str().to_str().unwrap();
 
     if args.only_extract_audio {
-        utils::ffmpeg_audio::extract_audio_from_video(input_video_path, tmp_path_str, 16000);
+        if let Err(e) = utils::ffmpeg_audio::extract_audio_segment_from_video(
+            input_video_path,
+            tmp_path_str,
+            16000,
+            &time_range,
+            &audio_selector,
+        ) {
+            println!("Failed to extract audio: {}", e);
+            return;
+        }
 
         // Generate a random name for the audio file based on timestamp
         let tmp_path = {
             let timestamp = chrono::Utc::now().timestamp();
-            format!("{}.audio.{}.wav", args.input_video_path, timestamp)
+            format!("{}.audio.{}.wav", input_video_path, timestamp)
         };
         // Copy the audio to the output path
         std::fs::copy(tmp_path_str, tmp_path.as_str()).unwrap();
@@ -156,39 +595,264 @@ fn main() {
     // Get the original subtitles
     let mut subtitles = match args.subtitle_source.as_str() {
         "audio" => {
-            utils::ffmpeg_audio::extract_audio_from_video(input_video_path, tmp_path_str, 16000);
-            let state: whisper_rs::WhisperState = if args.translator_backend == "whisper" {
-                if target_language != "en" {
-                    println!("Whisper only supports english translation");
+            // Decode in memory, the WAV file is only written with `--only-extract-audio`
+            let samples = match utils::ffmpeg_audio::decode_audio_segment_from_video(
+                input_video_path,
+                16000,
+                &time_range,
+                &audio_selector,
+            ) {
+                Ok(samples) => samples,
+                Err(e) => {
+                    println!("Failed to extract audio: {}", e);
                     return;
                 }
+            };
+            let whisper_options = whisper::experiment::WhisperOptions {
+                beam_size: args.whisper_beam_size,
+                temperature: args.whisper_temperature,
+                temperature_inc: args.whisper_temperature_inc,
+                n_threads: args.whisper_threads,
+                initial_prompt: args.whisper_initial_prompt.clone(),
+                no_context: args.whisper_no_context,
+                suppress_blank: args.whisper_suppress_blank,
+                suppress_non_speech_tokens: args.whisper_suppress_non_speech_tokens,
+                max_len: args.whisper_max_len,
+            };
+            if source_language == "auto" {
+                match whisper::experiment::detect_language_from_f32_16khz_samples(
+                    &args.ggml_model_path,
+                    &samples,
+                    args.language_detection_duration,
+                    &whisper_options,
+                ) {
+                    Ok(detected) => {
+                        println!(
+                            "Detected language: {} (probability: {:.2})",
+                            detected.code, detected.probability
+                        );
+                        source_language = detected.code;
+                    }
+                    Err(e) => {
+                        println!("Failed to detect the language: {}", e);
+                        return;
+                    }
+                }
+            }
+
+            let translate = translator.capabilities().speech;
+            if translate && !translator.supports_language(&source_language, &target_language) {
+                println!(
+                    "Unsupported language pair for {} now, {} -> {}",
+                    translator.name(),
+                    source_language,
+                    target_language
+                );
+                return;
+            }
+
+            let mut subtitles = if args.vad {
+                let mut detector = utils::vad::EnergyVoiceDetector {
+                    energy_threshold: args.vad_energy_threshold,
+                    max_zero_crossing_rate: args.vad_max_zero_crossing_rate,
+                };
+                let options = utils::vad::VadOptions {
+                    min_speech_duration: args.vad_min_speech_duration,
+                    min_silence_duration: args.vad_min_silence_duration,
+                    ..Default::default()
+                };
+                let regions =
+                    utils::vad::detect_speech_regions(&samples, 16000, &mut detector, &options);
+                println!("Found {} speech regions", regions.len());
 
-                // Transribe and translate the audio into subtitle directly (english only)
-                whisper::experiment::extract_and_translate_from_f32_16khz_wav_audio(
+                // Transcribe (and translate if whisper is the translator) the speech only
+                whisper::experiment::extract_and_translate_speech_regions_from_f32_16khz_samples(
                     &args.ggml_model_path,
-                    tmp_path_str,
+                    &samples,
+                    &regions,
                     &source_language,
-                    true,
+                    translate,
+                    &whisper_options,
+                    args.whisper_jobs,
                 )
-            } else {
-                // Transcribe the audio into subtitle, the translation will be done later
-                whisper::experiment::extract_from_f32_16khz_wav_audio(
+            } else if args.whisper_jobs > 1 {
+                let options = whisper::chunked::ChunkOptions {
+                    chunk_duration: args.whisper_chunk_duration,
+                    jobs: args.whisper_jobs,
+                    ..Default::default()
+                };
+                whisper::chunked::extract_and_translate_chunked_from_f32_16khz_samples(
                     &args.ggml_model_path,
-                    tmp_path_str,
+                    &samples,
                     &source_language,
+                    translate,
+                    &whisper_options,
+                    &options,
                 )
+            } else {
+                let state: whisper_rs::WhisperState = if translate {
+                    // Transribe and translate the audio into subtitle directly (english only)
+                    whisper::experiment::extract_and_translate_from_f32_16khz_samples(
+                        &args.ggml_model_path,
+                        &samples,
+                        &source_language,
+                        true,
+                        &whisper_options,
+                    )
+                } else {
+                    // Transcribe the audio into subtitle, the translation will be done later
+                    whisper::experiment::extract_from_f32_16khz_samples(
+                        &args.ggml_model_path,
+                        &samples,
+                        &source_language,
+                        &whisper_options,
+                    )
+                };
+                utils::whisper_state::create_subtitle_from_whisper_state(&state)
             };
-            utils::whisper_state::create_subtitle_from_whisper_state(&state)
+            // Whisper timestamps are relative to the extracted audio
+            time_range.offset_subtitles(&mut subtitles);
+
+            if args.whisper_filter {
+                let mut options = whisper::filter::FilterOptions::for_language(&source_language);
+                options.min_probability = args.whisper_min_probability;
+                if let Some(path) = &args.whisper_hallucinations_path {
+                    match std::fs::read_to_string(path) {
+                        Ok(content) => options.hallucinations.extend(
+                            content
+                                .lines()
+                                .map(|line| line.trim())
+                                .filter(|line| !line.is_empty())
+                                .map(|line| line.to_string()),
+                        ),
+                        Err(e) => {
+                            println!("Failed to read the hallucinations file: {}", e);
+                            return;
+                        }
+                    }
+                }
+                let (filtered, report) = whisper::filter::filter_subtitles(subtitles, &options);
+                for (subtitle, reason) in report.dropped.iter() {
+                    println!(
+                        "Dropped {:.2}s ({}): {}",
+                        subtitle.start,
+                        reason,
+                        subtitle.text.trim()
+                    );
+                }
+                println!(
+                    "Dropped {} subtitles and cut the repetitions of {}",
+                    report.dropped.len(),
+                    report.shortened
+                );
+                subtitles = filtered;
+            }
+            subtitles
+        }
+        "container" => {
+            let selector = if let Some(index) = args.subtitle_track {
+                utils::ffmpeg_stream::StreamSelector::Index(index)
+            } else if let Some(language) = args.subtitle_language.clone() {
+                utils::ffmpeg_stream::StreamSelector::Language(language)
+            } else {
+                utils::ffmpeg_stream::StreamSelector::Best
+            };
+            match utils::ffmpeg_subtitle::extract_subtitles_from_video(input_video_path, &selector)
+            {
+                Ok(subtitles) => subtitles,
+                Err(e) => {
+                    println!("Failed to extract subtitles from the container: {}", e);
+                    return;
+                }
+            }
+        }
+        "ocr" => {
+            if source_language == "auto" && args.ocr_language.is_none() {
+                println!(
+                    "The OCR language is required with auto source language, use --ocr-language"
+                );
+                return;
+            }
+            let region = match ocr::SubtitleRegion::parse(&args.ocr_region) {
+                Ok(region) => region,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+            let options = ocr::OcrOptions {
+                region,
+                sample_interval: args.ocr_interval,
+                change_threshold: args.ocr_change_threshold,
+                ..Default::default()
+            };
+            let mut engine = ocr::tesseract::TesseractEngine::new(
+                args.ocr_language
+                    .clone()
+                    .unwrap_or(ocr::tesseract::get_tesseract_language(&source_language)),
+            );
+
+            // Bitmap subtitle tracks are used if one is selected, hardcoded subtitles otherwise
+            let result = if let Some(index) = args.subtitle_track {
+                ocr::ffmpeg_bitmap::ocr_bitmap_subtitles_from_video(
+                    input_video_path,
+                    &utils::ffmpeg_stream::StreamSelector::Index(index),
+                    &mut engine,
+                )
+            } else if let Some(language) = args.subtitle_language.clone() {
+                ocr::ffmpeg_bitmap::ocr_bitmap_subtitles_from_video(
+                    input_video_path,
+                    &utils::ffmpeg_stream::StreamSelector::Language(language),
+                    &mut engine,
+                )
+            } else {
+                ocr::ffmpeg_frames::ocr_hardcoded_subtitles_from_video(
+                    input_video_path,
+                    &mut engine,
+                    &options,
+                )
+            };
+            match result {
+                Ok(subtitles) => subtitles,
+                Err(e) => {
+                    println!("Failed to OCR subtitles: {}", e);
+                    return;
+                }
+            }
+        }
+        "file" => {
+            let Some(path) = args.input_subtitle_path.as_deref() else {
+                println!("The input subtitle path is required with file subtitle source, use --input-subtitle-path");
+                return;
+            };
+            match utils::subtitle_file::read_subtitle_file(
+                path,
+                args.input_subtitle_encoding.as_deref(),
+                &source_language,
+            ) {
+                Ok(subtitles) => subtitles,
+                Err(e) => {
+                    println!("Failed to read subtitles from {}: {}", path, e);
+                    return;
+                }
+            }
         }
         source => {
             println!("Unsupported subtitle source now, {}", source);
             return;
         }
     };
+    if !time_range.is_full() {
+        subtitles = time_range.clip_subtitles(subtitles);
+    }
     if subtitles.is_empty() {
         println!("No subtitles found");
         return;
     }
+    if let Some(profile) = &readability_profile {
+        // Translated by readable pieces, the lines are broken once the text is final
+        subtitles = utils::resegment::split_and_merge_subtitles(subtitles, profile);
+    }
 
     if args.only_transcript {
         // Save original subtitles
@@ -197,6 +861,9 @@ fn main() {
         } else {
             args.original_subtitle_path
         };
+        if let Some(profile) = &readability_profile {
+            utils::resegment::fit_subtitles(&mut subtitles, profile);
+        }
         let file = std::fs::File::create(tmp_path.as_str()).unwrap();
         let mut exporter = output::srt::SrtSubtitleExporter::new(file);
         exporter.output_subtitles(&subtitles);
@@ -207,88 +874,65 @@ fn main() {
     }
 
     // Translate the subtitles
-    match args.translator_backend.as_str() {
-        "whisper" => {
-            // Already translated if audio source is used
-            match args.subtitle_source.as_str() {
-                "audio" => {
-                    println!("Skipping - subtitles are already translated using whisper");
-                }
-                source => {
-                    println!(
-                        "Unsupported translator backend for the given input source now, {}",
-                        source
-                    );
-                    return;
-                }
-            };
-        }
-        "deepl" => {
-            let deepl_api_key = std::env::var("DEEPL_API_KEY").unwrap();
-            if deepl_api_key.is_empty() {
-                println!("DEEPL_API_KEY is not set");
-                return;
+    if translator.capabilities().speech {
+        // Already translated if audio source is used
+        match args.subtitle_source.as_str() {
+            "audio" => {
+                println!(
+                    "Skipping - subtitles are already translated using {}",
+                    translator.name()
+                );
             }
-
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            subtitles.iter_mut().for_each(|s| {
-                s.text = rt
-                    .block_on(translate::deepl::translate_text(
-                        deepl_api_key.as_str(),
-                        vec![s.text.as_str()],
-                        target_language.as_str(),
-                        Some(source_language.as_str()),
-                    ))
-                    .unwrap();
-            });
-        }
-        "llm" => {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            let model_name = args.llm_model_name.clone();
-            let api_base = args.llm_api_base;
-            let model_name_clone = model_name.clone();
-
-            let client = if !api_base.is_empty() {
-                // -- Build an auth_resolver and the AdapterConfig
-                // link https://github.com/jeremychone/rust-genai/blob/main/examples/c06-target-resolver.rs
-                let target_resolver = ServiceTargetResolver::from_resolver_fn(
-                    move | _service_target: ServiceTarget| -> Result<ServiceTarget, genai::resolver::Error> {
-                        let endpoint = Endpoint::from_owned(api_base.clone());
-                        let auth = AuthData::from_env("CUSTOM_API_KEY");
-                        let model = ModelIden::new(AdapterKind::OpenAI, model_name_clone);
-                        Ok(ServiceTarget { endpoint, auth, model })
-                    },
+            source => {
+                println!(
+                    "Unsupported translator backend for the given input source now, {}",
+                    source
                 );
-                Client::builder()
-                    .with_service_target_resolver(target_resolver)
-                    .build()
-            } else {
-                Client::default()
-            };
-            let system_prompt = if !args.llm_prompt.is_empty() {
-                args.llm_prompt.clone()
-            } else {
-                format!(
-                    "Translate the following text. to language {}",
-                    &target_language
-                )
-            };
-            subtitles.iter_mut().for_each(|s| {
-                s.text = rt
-                    .block_on(translate::llm::translate_text(
-                        &client,
-                        &model_name,
-                        &system_prompt,
-                        vec![s.text.as_str()],
-                    ))
-                    .unwrap();
-            });
-        }
-        // more translators can be added here
-        translator => {
-            println!("Unsupported translator backend now {}", translator);
+                return;
+            }
+        };
+    } else {
+        if !translator.supports_language(&source_language, &target_language) {
+            println!(
+                "Unsupported language pair for {} now, {} -> {}",
+                translator.name(),
+                source_language,
+                target_language
+            );
             return;
         }
+
+        let capabilities = translator.capabilities();
+        println!(
+            "Translating with {} (batch: {}, context: {})",
+            translator.name(),
+            capabilities.batch,
+            capabilities.context
+        );
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let translations = match rt.block_on(translator.translate(
+            subtitles.iter().map(|s| s.text.as_str()).collect(),
+            &source_language,
+            &target_language,
+        )) {
+            Ok(translations) => translations,
+            Err(e) => {
+                println!("Failed to translate subtitles: {}", e);
+                return;
+            }
+        };
+        subtitles
+            .iter_mut()
+            .zip(translations)
+            .for_each(|(s, translation)| {
+                s.original_text = Some(std::mem::replace(&mut s.text, translation));
+                // The timed words are the ones of the transcript
+                s.words.clear();
+            });
+    }
+
+    if let Some(profile) = &readability_profile {
+        utils::resegment::fit_subtitles(&mut subtitles, profile);
     }
 
     // Save the translated subtitles
@@ -301,21 +945,163 @@ fn main() {
         };
         let file = std::fs::File::create(tmp_path.as_str()).unwrap();
         let mut exporter = output::srt::SrtSubtitleExporter::new(file);
+        exporter.bilingual = args.bilingual;
         exporter.output_subtitles(&subtitles);
 
         if args.only_translate {
             // This might be confusing, but we return here to avoid any other post-processing
             println!("Done, translated subtitles saved to {}", tmp_path);
         }
+    } else if args.subtitle_backend == "vtt" {
+        let tmp_path = args
+            .subtitle_output_path
+            .unwrap_or(input_video_path.to_string() + ".vtt");
+        let cue_settings = match output::vtt::VttCueSettings::parse(&args.vtt_cue_settings) {
+            Ok(cue_settings) => cue_settings,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        let style = match args.vtt_style_path.map(std::fs::read_to_string).transpose() {
+            Ok(style) => style,
+            Err(e) => {
+                println!("Failed to read the WebVTT style file: {}", e);
+                return;
+            }
+        };
+        let file = std::fs::File::create(tmp_path.as_str()).unwrap();
+        let mut exporter = output::vtt::VttSubtitleExporter::new(file);
+        exporter.cue_settings = cue_settings;
+        exporter.style = style;
+        exporter.notes.extend(args.vtt_note);
+        exporter.bilingual = args.bilingual;
+        exporter.output_subtitles(&subtitles);
+        println!("Done, translated subtitles saved to {}", tmp_path);
+    } else if args.subtitle_backend == "ass" {
+        let styles = match load_ass_styles(args.ass_style_path.as_deref(), &args.ass_style) {
+            Ok(styles) => styles,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        let tmp_path = args
+            .subtitle_output_path
+            .unwrap_or(input_video_path.to_string() + ".ass");
+        let file = std::fs::File::create(tmp_path.as_str()).unwrap();
+        let mut exporter = output::ass::AssSubtitleExporter::new(file);
+        exporter.styles = styles;
+        exporter.karaoke = args.ass_karaoke;
+        exporter.bilingual = args.bilingual;
+        exporter.output_subtitles(&subtitles);
+        println!("Done, translated subtitles saved to {}", tmp_path);
     } else if args.subtitle_backend == "container" {
-        // Save the translated subtitles to the video container (inplace if not specified)
+        // Save the translated subtitles to the video container (in place if not specified, with --in-place)
         let mut exporter = output::ffmpeg_subtitle::VideoSubtitleTrackExporter::new(
             input_video_path.to_string(),
             args.subtitle_output_path
                 .unwrap_or(input_video_path.to_string()),
         );
-        exporter.output_subtitles(&subtitles);
+        if args.in_place_backup {
+            exporter = exporter.with_backup(format!("{}.bak", input_video_path));
+        }
+        if args.trim_video && !time_range.is_full() {
+            exporter = exporter.with_trim_range(time_range);
+        }
+        if let Some(name) = &args.container_subtitle_codec {
+            match output::ffmpeg_subtitle::parse_subtitle_codec(name) {
+                Ok(codec) => exporter = exporter.with_subtitle_codec(codec),
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            }
+        }
+        // The translated track is the default one, tagged with the languages for players
+        let mut disposition = ffmpeg_next::format::stream::Disposition::DEFAULT;
+        if args.container_track_forced {
+            disposition |= ffmpeg_next::format::stream::Disposition::FORCED;
+        }
+        let mut track = output::ffmpeg_subtitle::SubtitleTrack::new(subtitles.clone())
+            .with_language(&target_language)
+            .with_disposition(disposition);
+        if let Some(title) = args.container_track_title {
+            track = track.with_title(title);
+        }
+        let mut tracks = vec![track];
+        let original_subtitles = output::original_subtitles(&subtitles);
+        if args.bilingual && original_subtitles.is_empty() {
+            // e.g. the whisper translator does not keep the original text
+            println!("No original text to mux, only the translated track is added");
+        } else if args.bilingual {
+            let mut original_track =
+                output::ffmpeg_subtitle::SubtitleTrack::new(original_subtitles);
+            if source_language != "auto" {
+                original_track = original_track.with_language(&source_language);
+            }
+            tracks.push(original_track);
+        }
+        if let Err(e) = exporter.output_tracks(&tracks) {
+            println!("Failed to mux subtitles: {}", e);
+            return;
+        }
+    } else if args.subtitle_backend == "embedded" {
+        let styles = match load_ass_styles(args.ass_style_path.as_deref(), &args.ass_style) {
+            Ok(styles) => styles,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        // Re-encoding is lossy, so the input video is kept unless explicitly asked
+        let out_video_path = args.subtitle_output_path.unwrap_or_else(|| {
+            if args.in_place {
+                return input_video_path.to_string();
+            }
+            let path = std::path::Path::new(input_video_path);
+            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("mp4");
+            path.with_extension(format!("embedded.{}", extension))
+                .to_string_lossy()
+                .to_string()
+        });
+        let mut exporter = output::ffmpeg_burn_in::VideoSubtitleBurnInExporter::new(
+            input_video_path.to_string(),
+            out_video_path.clone(),
+        );
+        exporter.codec = args.video_codec;
+        exporter.crf = Some(args.video_crf);
+        exporter.preset = Some(args.video_preset);
+        exporter.styles = styles;
+        exporter.bilingual = args.bilingual;
+        if args.in_place_backup {
+            exporter.backup_path = Some(format!("{}.bak", input_video_path));
+        }
+        if let Err(e) = exporter.output_video(&subtitles) {
+            println!("Failed to burn subtitles: {}", e);
+            return;
+        }
+        println!(
+            "Done, video with embedded subtitles saved to {}",
+            out_video_path
+        );
     } else {
         println!("Unsupported subtitle backend now");
     }
 }
+
+/// Load the ASS styles from the style file (or the default one), with overrides on the first
+fn load_ass_styles(
+    style_path: Option<&str>,
+    overrides: &str,
+) -> Result<Vec<output::ass::AssStyle>, String> {
+    let mut styles = match style_path {
+        Some(style_path) => {
+            let content = std::fs::read_to_string(style_path).map_err(|e| e.to_string())?;
+            output::ass::parse_ass_styles(&content)?
+        }
+        None => vec![output::ass::AssStyle::default()],
+    };
+    styles[0].apply_overrides(overrides)?;
+    Ok(styles)
+}
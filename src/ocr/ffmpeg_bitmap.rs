@@ -0,0 +1,132 @@
+use ffmpeg::codec::subtitle::{Bitmap, Rect};
+use ffmpeg::format::input;
+use ffmpeg_next as ffmpeg;
+
+use super::{GrayImage, OcrEngine};
+use crate::utils::ffmpeg_stream::{list_streams, select_stream, StreamSelector};
+use crate::utils::ffmpeg_subtitle::is_bitmap_subtitle_codec;
+use crate::utils::Subtitle;
+
+/// Display duration of the last bitmap subtitle if the stream does not tell it
+const DEFAULT_DISPLAY_DURATION: f32 = 5.0;
+
+/// Render a palettized subtitle bitmap (PGS, VobSub, DVB) to grayscale, transparent as black
+fn bitmap_to_gray_image(bitmap: &Bitmap) -> Option<GrayImage> {
+    let (width, height) = (bitmap.width(), bitmap.height());
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let mut data = Vec::with_capacity((width * height) as usize);
+    unsafe {
+        let rect = bitmap.as_ptr();
+        let indices = (*rect).data[0];
+        let palette = (*rect).data[1] as *const u32;
+        let linesize = (*rect).linesize[0] as usize;
+        if indices.is_null() || palette.is_null() {
+            return None;
+        }
+
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                // The palette is in native-endian ARGB
+                let argb = *palette.add(*indices.add(y * linesize + x) as usize);
+                let alpha = (argb >> 24) & 0xff;
+                let r = (argb >> 16) & 0xff;
+                let g = (argb >> 8) & 0xff;
+                let b = argb & 0xff;
+                let luma = (r * 299 + g * 587 + b * 114) / 1000;
+                data.push((luma * alpha / 255) as u8);
+            }
+        }
+    }
+    Some(GrayImage::new(width, height, data))
+}
+
+/// OCR the bitmap subtitles (PGS, VobSub, DVB) of a stream in the video
+pub fn ocr_bitmap_subtitles_from_video(
+    video_path: &str,
+    selector: &StreamSelector,
+    engine: &mut dyn OcrEngine,
+) -> Result<Vec<Subtitle>, ffmpeg::Error> {
+    ffmpeg::init().unwrap();
+
+    let mut ictx = input(video_path)?;
+    let bitmap_streams: Vec<_> = list_streams(&ictx, ffmpeg::media::Type::Subtitle)
+        .into_iter()
+        .filter(|s| is_bitmap_subtitle_codec(s.codec))
+        .collect();
+    let stream_index =
+        select_stream(&bitmap_streams, selector).ok_or(ffmpeg::Error::StreamNotFound)?;
+    let stream = ictx
+        .stream(stream_index)
+        .ok_or(ffmpeg::Error::StreamNotFound)?;
+    let time_base = stream.time_base();
+    println!("Input: {:?}", stream_index);
+    println!("Input codec: {}", stream.parameters().id().name());
+
+    let context_decoder = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?;
+    let mut decoder = context_decoder.decoder();
+    decoder.set_packet_time_base(time_base);
+    let mut decoder = decoder.subtitle()?;
+
+    let mut subtitles = Vec::new();
+    // Start, optional end and text of the subtitle being displayed
+    let mut pending: Option<(f32, Option<f32>, String)> = None;
+    for (stream, packet) in ictx.packets() {
+        if stream.index() != stream_index {
+            continue;
+        }
+        let mut decoded = ffmpeg::Subtitle::new();
+        if !decoder.decode(&packet, &mut decoded)? {
+            continue;
+        }
+        let pts = match packet.pts().or(packet.dts()) {
+            Some(pts) => (pts as f64 * f64::from(time_base)) as f32,
+            None => continue,
+        };
+        let start = pts + decoded.start() as f32 / 1000.;
+
+        // Any new display set (including an empty one) ends the previous subtitle
+        if let Some((pending_start, pending_end, text)) = pending.take() {
+            let end = pending_end.map_or(start, |end| end.min(start));
+            subtitles.push(Subtitle::new(pending_start, end, text));
+        }
+
+        let text = decoded
+            .rects()
+            .filter_map(|rect| match rect {
+                Rect::Bitmap(bitmap) => bitmap_to_gray_image(&bitmap),
+                _ => None,
+            })
+            .filter_map(|image| match engine.recognize(&image) {
+                Ok(text) => Some(text.trim().to_string()),
+                Err(e) => {
+                    println!("OCR failed at {:.3}s: {}", start, e);
+                    None
+                }
+            })
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<String>>()
+            .join("\n");
+        if text.is_empty() {
+            continue;
+        }
+
+        let end = if decoded.end() > decoded.start() && decoded.end() != u32::MAX {
+            Some(pts + decoded.end() as f32 / 1000.)
+        } else {
+            None
+        };
+        pending = Some((start, end, text));
+    }
+    if let Some((start, end, text)) = pending {
+        subtitles.push(Subtitle::new(
+            start,
+            end.unwrap_or(start + DEFAULT_DISPLAY_DURATION),
+            text,
+        ));
+    }
+
+    Ok(subtitles)
+}
@@ -0,0 +1,118 @@
+use ffmpeg::format::{input, Pixel};
+use ffmpeg::media::Type;
+use ffmpeg::software::scaling::{context::Context as Scaler, flag::Flags};
+use ffmpeg_next::{self as ffmpeg, frame, Rational};
+
+use super::{GrayImage, OcrEngine, OcrOptions, SubtitleRegion, TextChangeSegmenter};
+use crate::utils::Subtitle;
+
+struct FrameSampler<'a> {
+    scaler: Scaler,
+    time_base: Rational,
+    region: &'a SubtitleRegion,
+    sample_interval: f32,
+    next_sample_time: f32,
+    last_time: f32,
+}
+
+impl FrameSampler<'_> {
+    fn receive_frames<F: FnMut(f32, GrayImage)>(
+        &mut self,
+        decoder: &mut ffmpeg::decoder::Video,
+        on_frame: &mut F,
+    ) {
+        let mut decoded = frame::Video::empty();
+        while decoder.receive_frame(&mut decoded).is_ok() {
+            let time = match decoded.timestamp().or(decoded.pts()) {
+                Some(ts) => (ts as f64 * f64::from(self.time_base)) as f32,
+                None => continue,
+            };
+            self.last_time = self.last_time.max(time);
+            if time < self.next_sample_time {
+                continue;
+            }
+            self.next_sample_time = time + self.sample_interval;
+
+            let mut gray = frame::Video::empty();
+            self.scaler.run(&decoded, &mut gray).unwrap();
+
+            // Copy the luma plane without the stride padding
+            let (width, height) = (gray.width(), gray.height());
+            let stride = gray.stride(0);
+            let mut data = Vec::with_capacity((width * height) as usize);
+            for row in gray.data(0).chunks(stride).take(height as usize) {
+                data.extend_from_slice(&row[..width as usize]);
+            }
+            on_frame(time, GrayImage::new(width, height, data).crop(self.region));
+        }
+    }
+}
+
+/// Sample the subtitle region of the video frames every `sample_interval` seconds
+///
+/// Returns the timestamp of the last decoded frame.
+pub fn sample_frames_from_video<F: FnMut(f32, GrayImage)>(
+    video_path: &str,
+    region: &SubtitleRegion,
+    sample_interval: f32,
+    mut on_frame: F,
+) -> Result<f32, ffmpeg::Error> {
+    ffmpeg::init().unwrap();
+
+    let mut ictx = input(video_path)?;
+    let stream = ictx
+        .streams()
+        .best(Type::Video)
+        .ok_or(ffmpeg::Error::StreamNotFound)?;
+    let stream_index = stream.index();
+    let time_base = stream.time_base();
+    println!("Input: {:?}", stream_index);
+    println!("Input codec: {}", stream.parameters().id().name());
+
+    let context_decoder = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?;
+    let mut decoder = context_decoder.decoder().video()?;
+
+    let mut sampler = FrameSampler {
+        scaler: Scaler::get(
+            decoder.format(),
+            decoder.width(),
+            decoder.height(),
+            Pixel::GRAY8,
+            decoder.width(),
+            decoder.height(),
+            Flags::BILINEAR,
+        )?,
+        time_base,
+        region,
+        sample_interval,
+        next_sample_time: 0.0,
+        last_time: 0.0,
+    };
+
+    for (stream, packet) in ictx.packets() {
+        if stream.index() == stream_index {
+            decoder.send_packet(&packet)?;
+            sampler.receive_frames(&mut decoder, &mut on_frame);
+        }
+    }
+    decoder.send_eof()?;
+    sampler.receive_frames(&mut decoder, &mut on_frame);
+
+    Ok(sampler.last_time)
+}
+
+/// OCR the hardcoded (burned-in) subtitles of the video
+pub fn ocr_hardcoded_subtitles_from_video(
+    video_path: &str,
+    engine: &mut dyn OcrEngine,
+    options: &OcrOptions,
+) -> Result<Vec<Subtitle>, ffmpeg::Error> {
+    let mut segmenter = TextChangeSegmenter::new(engine, options);
+    let end = sample_frames_from_video(
+        video_path,
+        &options.region,
+        options.sample_interval,
+        |time, image| segmenter.push(time, &image),
+    )?;
+    Ok(segmenter.finish(end + options.sample_interval))
+}
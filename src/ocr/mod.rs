@@ -0,0 +1,325 @@
+use std::error::Error;
+
+use crate::utils::Subtitle;
+
+pub mod ffmpeg_bitmap;
+pub mod ffmpeg_frames;
+pub mod tesseract;
+
+/// Pixel value above which a pixel is considered part of the (bright) subtitle text
+const TEXT_LUMA_THRESHOLD: u8 = 200;
+
+/// 8-bit grayscale image, row-major without padding
+#[derive(Clone, Debug)]
+pub struct GrayImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl GrayImage {
+    pub fn new(width: u32, height: u32, data: Vec<u8>) -> GrayImage {
+        assert_eq!(data.len(), (width * height) as usize);
+        GrayImage {
+            width,
+            height,
+            data,
+        }
+    }
+
+    pub fn crop(&self, region: &SubtitleRegion) -> GrayImage {
+        let (x, y, width, height) = region.to_pixels(self.width, self.height);
+        let mut data = Vec::with_capacity((width * height) as usize);
+        for row in y..y + height {
+            let offset = (row * self.width + x) as usize;
+            data.extend_from_slice(&self.data[offset..offset + width as usize]);
+        }
+        GrayImage::new(width, height, data)
+    }
+
+    /// Mask of the pixels which are bright enough to be subtitle text
+    pub fn text_mask(&self) -> Vec<bool> {
+        self.data
+            .iter()
+            .map(|&p| p >= TEXT_LUMA_THRESHOLD)
+            .collect()
+    }
+
+    /// Encode as a binary PGM (P5), which most OCR engines can read
+    pub fn to_pgm(&self) -> Vec<u8> {
+        let mut pgm = format!("P5\n{} {}\n255\n", self.width, self.height).into_bytes();
+        pgm.extend_from_slice(&self.data);
+        pgm
+    }
+}
+
+/// Part of the frame in which the subtitles are expected, as fractions of the frame size
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubtitleRegion {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for SubtitleRegion {
+    fn default() -> Self {
+        // The bottom quarter of the frame
+        SubtitleRegion {
+            x: 0.0,
+            y: 0.75,
+            width: 1.0,
+            height: 0.25,
+        }
+    }
+}
+
+impl SubtitleRegion {
+    /// Parse a region given as "x,y,width,height" in fractions of the frame size
+    pub fn parse(region: &str) -> Result<SubtitleRegion, Box<dyn Error>> {
+        let values = region
+            .split(',')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()?;
+        if values.len() != 4 {
+            return Err(format!("Invalid region {}, expected x,y,width,height", region).into());
+        }
+        if values.iter().any(|v| !(0.0..=1.0).contains(v))
+            || values[0] + values[2] > 1.0
+            || values[1] + values[3] > 1.0
+        {
+            return Err(format!("Region {} is out of the frame", region).into());
+        }
+        Ok(SubtitleRegion {
+            x: values[0],
+            y: values[1],
+            width: values[2],
+            height: values[3],
+        })
+    }
+
+    fn to_pixels(&self, frame_width: u32, frame_height: u32) -> (u32, u32, u32, u32) {
+        let x = ((self.x * frame_width as f32) as u32).min(frame_width.saturating_sub(1));
+        let y = ((self.y * frame_height as f32) as u32).min(frame_height.saturating_sub(1));
+        let width = ((self.width * frame_width as f32) as u32).clamp(1, frame_width - x);
+        let height = ((self.height * frame_height as f32) as u32).clamp(1, frame_height - y);
+        (x, y, width, height)
+    }
+}
+
+pub trait OcrEngine {
+    /// Recognize the text in the image, an empty string if there is none
+    fn recognize(&mut self, image: &GrayImage) -> Result<String, Box<dyn Error>>;
+}
+
+pub struct OcrOptions {
+    pub region: SubtitleRegion,
+    /// Interval between two sampled frames, in seconds
+    pub sample_interval: f32,
+    /// Ratio of changed text pixels above which the subtitle is considered changed
+    pub change_threshold: f32,
+    /// Ratio of text pixels below which the region is considered empty (no OCR is run)
+    pub min_text_ratio: f32,
+}
+
+impl Default for OcrOptions {
+    fn default() -> Self {
+        OcrOptions {
+            region: SubtitleRegion::default(),
+            sample_interval: 0.5,
+            change_threshold: 0.2,
+            min_text_ratio: 0.002,
+        }
+    }
+}
+
+struct CurrentText {
+    start: f32,
+    mask: Vec<bool>,
+    text: String,
+}
+
+/// Turn sampled subtitle regions into timed subtitles
+///
+/// OCR only runs when the text pixels change, consecutive samples with the same
+/// recognized text are merged into a single subtitle.
+pub struct TextChangeSegmenter<'a> {
+    engine: &'a mut dyn OcrEngine,
+    change_threshold: f32,
+    min_text_ratio: f32,
+    current: Option<CurrentText>,
+    subtitles: Vec<Subtitle>,
+}
+
+fn mask_difference(a: &[bool], b: &[bool]) -> f32 {
+    if a.len() != b.len() {
+        return 1.0;
+    }
+    let (changed, union) = a
+        .iter()
+        .zip(b.iter())
+        .fold((0usize, 0usize), |(changed, union), (&a, &b)| {
+            (changed + (a != b) as usize, union + (a || b) as usize)
+        });
+    if union == 0 {
+        0.0
+    } else {
+        changed as f32 / union as f32
+    }
+}
+
+impl<'a> TextChangeSegmenter<'a> {
+    pub fn new(engine: &'a mut dyn OcrEngine, options: &OcrOptions) -> TextChangeSegmenter<'a> {
+        TextChangeSegmenter {
+            engine,
+            change_threshold: options.change_threshold,
+            min_text_ratio: options.min_text_ratio,
+            current: None,
+            subtitles: Vec::new(),
+        }
+    }
+
+    /// Feed the (cropped) subtitle region sampled at the given time
+    pub fn push(&mut self, time: f32, image: &GrayImage) {
+        let mask = image.text_mask();
+        if let Some(current) = &self.current {
+            if mask_difference(&current.mask, &mask) < self.change_threshold {
+                return;
+            }
+        }
+
+        self.close(time);
+        let text_ratio = mask.iter().filter(|&&m| m).count() as f32 / mask.len().max(1) as f32;
+        let text = if text_ratio < self.min_text_ratio {
+            String::new()
+        } else {
+            match self.engine.recognize(image) {
+                Ok(text) => text.trim().to_string(),
+                Err(e) => {
+                    println!("OCR failed at {:.3}s: {}", time, e);
+                    String::new()
+                }
+            }
+        };
+        self.current = Some(CurrentText {
+            start: time,
+            mask,
+            text,
+        });
+    }
+
+    fn close(&mut self, end: f32) {
+        let Some(current) = self.current.take() else {
+            return;
+        };
+        if current.text.is_empty() {
+            return;
+        }
+        match self.subtitles.last_mut() {
+            // Small flickers (e.g. fades) should not split a subtitle
+            Some(last) if last.text == current.text && current.start - last.end < 0.001 => {
+                last.end = end;
+            }
+            _ => self
+                .subtitles
+                .push(Subtitle::new(current.start, end, current.text)),
+        }
+    }
+
+    /// End the last subtitle at the given time and return all subtitles
+    pub fn finish(mut self, end: f32) -> Vec<Subtitle> {
+        self.close(end);
+        self.subtitles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Recognize the column of the first text pixel as the text
+    struct FakeEngine {
+        calls: usize,
+    }
+
+    impl OcrEngine for FakeEngine {
+        fn recognize(&mut self, image: &GrayImage) -> Result<String, Box<dyn Error>> {
+            self.calls += 1;
+            let column = image
+                .text_mask()
+                .iter()
+                .position(|&m| m)
+                .map(|p| p as u32 % image.width);
+            Ok(column.map(|c| format!("text at {}", c)).unwrap_or_default())
+        }
+    }
+
+    fn frame_with_text(column: Option<u32>) -> GrayImage {
+        let (width, height) = (16, 8);
+        let mut data = vec![0u8; (width * height) as usize];
+        if let Some(column) = column {
+            // Text is only drawn in the bottom half of the frame
+            for row in height / 2..height {
+                data[(row * width + column) as usize] = 255;
+                data[(row * width + column + 1) as usize] = 255;
+            }
+        }
+        GrayImage::new(width, height, data)
+    }
+
+    #[test]
+    fn test_crop() {
+        let frame = frame_with_text(Some(2));
+        let region = SubtitleRegion {
+            x: 0.0,
+            y: 0.5,
+            width: 0.5,
+            height: 0.5,
+        };
+        let cropped = frame.crop(&region);
+        assert_eq!(cropped.width, 8);
+        assert_eq!(cropped.height, 4);
+        assert_eq!(cropped.text_mask().iter().filter(|&&m| m).count(), 8);
+    }
+
+    #[test]
+    fn test_parse_region() {
+        assert_eq!(
+            SubtitleRegion::parse("0, 0.75, 1, 0.25").unwrap(),
+            SubtitleRegion::default()
+        );
+        assert!(SubtitleRegion::parse("0,0.75,1").is_err());
+        assert!(SubtitleRegion::parse("0,0.8,1,0.5").is_err());
+    }
+
+    #[test]
+    fn test_text_change_segmenter() {
+        let mut engine = FakeEngine { calls: 0 };
+        let options = OcrOptions::default();
+        let frames = [
+            None,
+            Some(2),
+            Some(2),
+            Some(2),
+            Some(9),
+            Some(9),
+            None,
+            None,
+        ];
+
+        let mut segmenter = TextChangeSegmenter::new(&mut engine, &options);
+        for (i, column) in frames.iter().enumerate() {
+            let frame = frame_with_text(*column).crop(&options.region);
+            segmenter.push(i as f32 * 0.5, &frame);
+        }
+        let subtitles = segmenter.finish(4.0);
+
+        assert_eq!(subtitles.len(), 2);
+        assert_eq!(subtitles[0].text, "text at 2");
+        assert_eq!((subtitles[0].start, subtitles[0].end), (0.5, 2.0));
+        assert_eq!(subtitles[1].text, "text at 9");
+        assert_eq!((subtitles[1].start, subtitles[1].end), (2.0, 3.0));
+        // OCR only runs on text changes
+        assert_eq!(engine.calls, 2);
+    }
+}
@@ -0,0 +1,107 @@
+use std::error::Error;
+use std::process::Command;
+
+use tempfile::TempDir;
+
+use super::{GrayImage, OcrEngine, TEXT_LUMA_THRESHOLD};
+use crate::utils::is_cjk;
+
+/// OCR engine running a local `tesseract` executable
+pub struct TesseractEngine {
+    language: String,
+    tmp_dir: TempDir,
+}
+
+impl TesseractEngine {
+    pub fn new(language: String) -> TesseractEngine {
+        TesseractEngine {
+            language,
+            tmp_dir: TempDir::new().unwrap(),
+        }
+    }
+}
+
+/// Map the languages used on the command line to tesseract traineddata names
+pub fn get_tesseract_language(language: &str) -> String {
+    match language.to_lowercase().as_str() {
+        "en" => "eng",
+        "es" => "spa",
+        "fr" => "fra",
+        "de" => "deu",
+        "it" => "ita",
+        "ja" => "jpn",
+        "ko" => "kor",
+        "pt" => "por",
+        "ru" => "rus",
+        "zh" => "chi_sim",
+        "tw" | "zh-tw" => "chi_tra",
+        lang => lang,
+    }
+    .to_string()
+}
+
+/// Tesseract separates CJK characters with spaces, remove them
+fn remove_cjk_spaces(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    chars
+        .iter()
+        .enumerate()
+        .filter(|&(i, &c)| {
+            !(c == ' '
+                && i > 0
+                && is_cjk(chars[i - 1])
+                && chars.get(i + 1).is_some_and(|&next| is_cjk(next)))
+        })
+        .map(|(_, &c)| c)
+        .collect()
+}
+
+impl OcrEngine for TesseractEngine {
+    fn recognize(&mut self, image: &GrayImage) -> Result<String, Box<dyn Error>> {
+        // Tesseract works best with dark text on a light background
+        let binarized = GrayImage::new(
+            image.width,
+            image.height,
+            image
+                .data
+                .iter()
+                .map(|&p| if p >= TEXT_LUMA_THRESHOLD { 0 } else { 255 })
+                .collect(),
+        );
+        let image_path = self.tmp_dir.path().join("region.pgm");
+        std::fs::write(&image_path, binarized.to_pgm())?;
+
+        let output = Command::new("tesseract")
+            .arg(&image_path)
+            .arg("stdout")
+            .args(["-l", self.language.as_str(), "--psm", "6"])
+            .output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "tesseract failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )
+            .into());
+        }
+
+        let text = String::from_utf8_lossy(&output.stdout);
+        Ok(text
+            .lines()
+            .map(|line| remove_cjk_spaces(line.trim()))
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<String>>()
+            .join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_cjk_spaces() {
+        assert_eq!(remove_cjk_spaces("こ ん に ち は"), "こんにちは");
+        assert_eq!(remove_cjk_spaces("Hello world"), "Hello world");
+        assert_eq!(remove_cjk_spaces("東京 Tokyo"), "東京 Tokyo");
+    }
+}
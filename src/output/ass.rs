@@ -0,0 +1,488 @@
+use crate::output::OutputSubtitles;
+use crate::output::Subtitle;
+use crate::utils::overrides;
+use std::fs::File;
+use std::io::Write;
+
+const STYLE_FORMAT: &str = "Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, \
+    OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, \
+    Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding";
+
+/// Style of the original texts in bilingual mode, added from the first style if missing
+pub const ORIGINAL_STYLE: &str = "Original";
+
+/// A `[V4+ Styles]` style, colours are kept in the ASS `&HAABBGGRR` notation
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssStyle {
+    pub name: String,
+    pub fontname: String,
+    pub fontsize: f32,
+    pub primary_colour: String,
+    pub secondary_colour: String,
+    pub outline_colour: String,
+    pub back_colour: String,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strike_out: bool,
+    pub scale_x: f32,
+    pub scale_y: f32,
+    pub spacing: f32,
+    pub angle: f32,
+    pub border_style: u8,
+    pub outline: f32,
+    pub shadow: f32,
+    pub alignment: u8,
+    pub margin_l: u32,
+    pub margin_r: u32,
+    pub margin_v: u32,
+    pub encoding: u32,
+}
+
+impl Default for AssStyle {
+    fn default() -> Self {
+        // White text with a black outline at the bottom center, sized for a 1080p script
+        AssStyle {
+            name: "Default".to_string(),
+            fontname: "Arial".to_string(),
+            fontsize: 72.0,
+            primary_colour: "&H00FFFFFF".to_string(),
+            secondary_colour: "&H000000FF".to_string(),
+            outline_colour: "&H00000000".to_string(),
+            back_colour: "&H80000000".to_string(),
+            bold: false,
+            italic: false,
+            underline: false,
+            strike_out: false,
+            scale_x: 100.0,
+            scale_y: 100.0,
+            spacing: 0.0,
+            angle: 0.0,
+            border_style: 1,
+            outline: 3.0,
+            shadow: 1.0,
+            alignment: 2,
+            margin_l: 40,
+            margin_r: 40,
+            margin_v: 60,
+            encoding: 1,
+        }
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    overrides::parse_field("style", key, value)
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, String> {
+    // ASS uses -1 for true, but 1 is accepted by most renderers
+    Ok(parse_value::<i32>(key, value)? != 0)
+}
+
+impl AssStyle {
+    /// Set a field by its name in the `Format:` line (case insensitive)
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        match key.trim().to_lowercase().as_str() {
+            "name" => self.name = value.to_string(),
+            "fontname" => self.fontname = value.to_string(),
+            "fontsize" => self.fontsize = parse_value(key, value)?,
+            "primarycolour" => self.primary_colour = value.to_string(),
+            "secondarycolour" => self.secondary_colour = value.to_string(),
+            "outlinecolour" | "tertiarycolour" => self.outline_colour = value.to_string(),
+            "backcolour" => self.back_colour = value.to_string(),
+            "bold" => self.bold = parse_bool(key, value)?,
+            "italic" => self.italic = parse_bool(key, value)?,
+            "underline" => self.underline = parse_bool(key, value)?,
+            "strikeout" => self.strike_out = parse_bool(key, value)?,
+            "scalex" => self.scale_x = parse_value(key, value)?,
+            "scaley" => self.scale_y = parse_value(key, value)?,
+            "spacing" => self.spacing = parse_value(key, value)?,
+            "angle" => self.angle = parse_value(key, value)?,
+            "borderstyle" => self.border_style = parse_value(key, value)?,
+            "outline" => self.outline = parse_value(key, value)?,
+            "shadow" => self.shadow = parse_value(key, value)?,
+            "alignment" => self.alignment = parse_value(key, value)?,
+            "marginl" => self.margin_l = parse_value(key, value)?,
+            "marginr" => self.margin_r = parse_value(key, value)?,
+            "marginv" => self.margin_v = parse_value(key, value)?,
+            "encoding" => self.encoding = parse_value(key, value)?,
+            _ => return Err(format!("Unknown style field {}", key)),
+        }
+        Ok(())
+    }
+
+    /// Apply overrides in the `force_style` syntax, e.g. "Fontname=Noto Sans CJK JP,Fontsize=60"
+    pub fn apply_overrides(&mut self, overrides: &str) -> Result<(), String> {
+        overrides::apply_overrides("style", overrides, |key, value| self.set(key, value))
+    }
+
+    fn format(&self) -> String {
+        let flag = |value: bool| if value { "-1" } else { "0" };
+        format!(
+            "Style: {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.name,
+            self.fontname,
+            self.fontsize,
+            self.primary_colour,
+            self.secondary_colour,
+            self.outline_colour,
+            self.back_colour,
+            flag(self.bold),
+            flag(self.italic),
+            flag(self.underline),
+            flag(self.strike_out),
+            self.scale_x,
+            self.scale_y,
+            self.spacing,
+            self.angle,
+            self.border_style,
+            self.outline,
+            self.shadow,
+            self.alignment,
+            self.margin_l,
+            self.margin_r,
+            self.margin_v,
+            self.encoding
+        )
+    }
+}
+
+/// Parse the styles of a `[V4+ Styles]` section (e.g. exported from Aegisub)
+pub fn parse_ass_styles(content: &str) -> Result<Vec<AssStyle>, String> {
+    let mut styles = Vec::new();
+    let mut in_styles = false;
+    let mut format: Vec<String> = STYLE_FORMAT
+        .split(',')
+        .map(|f| f.trim().to_string())
+        .collect();
+    for line in content
+        .lines()
+        .map(|line| line.trim_start_matches('\u{feff}').trim())
+    {
+        if line.starts_with('[') {
+            in_styles = line.eq_ignore_ascii_case("[V4+ Styles]")
+                || line.eq_ignore_ascii_case("[V4 Styles]");
+            continue;
+        }
+        if !in_styles {
+            continue;
+        }
+        if let Some(fields) = line.strip_prefix("Format:") {
+            format = fields.split(',').map(|f| f.trim().to_string()).collect();
+        } else if let Some(values) = line.strip_prefix("Style:") {
+            let values: Vec<&str> = values.splitn(format.len(), ',').collect();
+            if values.len() != format.len() {
+                return Err(format!("Invalid style line {}", line));
+            }
+            let mut style = AssStyle::default();
+            for (key, value) in format.iter().zip(values) {
+                // Fields of the legacy V4 format without equivalent are ignored
+                if !key.eq_ignore_ascii_case("alphalevel") {
+                    style.set(key, value)?;
+                }
+            }
+            styles.push(style);
+        }
+    }
+    if styles.is_empty() {
+        return Err("No style found".to_string());
+    }
+    Ok(styles)
+}
+
+pub(crate) struct AssSubtitleExporter {
+    pub file: File,
+    pub title: String,
+    pub play_res_x: u32,
+    pub play_res_y: u32,
+    /// The first style is used by subtitles without one
+    pub styles: Vec<AssStyle>,
+    /// Highlight the words as they are spoken, if the subtitles have timed words
+    pub karaoke: bool,
+    /// Also write the original texts with the `Original` style, at the top by default
+    pub bilingual: bool,
+}
+
+impl AssSubtitleExporter {
+    pub fn new(file: File) -> AssSubtitleExporter {
+        AssSubtitleExporter {
+            file,
+            title: "AI no jimaku gumi".to_string(),
+            play_res_x: 1920,
+            play_res_y: 1080,
+            styles: vec![AssStyle::default()],
+            karaoke: false,
+            bilingual: false,
+        }
+    }
+}
+
+fn format_time(time: f32) -> String {
+    let centis = (time.max(0.0) * 100.0).round() as u64;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        centis / 360_000,
+        centis % 360_000 / 6000,
+        centis % 6000 / 100,
+        centis % 100
+    )
+}
+
+fn escape_text(text: &str) -> String {
+    // Braces would start an override block
+    text.trim()
+        .lines()
+        .map(|line| line.trim().replace('{', "(").replace('}', ")"))
+        .collect::<Vec<String>>()
+        .join("\\N")
+}
+
+/// Text with a `\k` tag before each word, the durations are in centiseconds
+fn karaoke_text(subtitle: &Subtitle) -> String {
+    let mut text = String::new();
+    let mut position = subtitle.start;
+    for (i, word) in subtitle.words.iter().enumerate() {
+        // Nothing is highlighted during the pauses between words
+        let pause = ((word.start - position) * 100.0).round();
+        if pause > 0.0 {
+            text.push_str(&format!("{{\\k{}}}", pause as u32));
+        }
+        let duration = ((word.end - word.start.max(position)) * 100.0)
+            .round()
+            .max(0.0);
+        let word_text = if i == 0 {
+            word.text.trim_start()
+        } else {
+            word.text.as_str()
+        };
+        text.push_str(&format!(
+            "{{\\k{}}}{}",
+            duration as u32,
+            word_text.replace('{', "(").replace('}', ")")
+        ));
+        position = position.max(word.end);
+    }
+    text
+}
+
+impl OutputSubtitles for AssSubtitleExporter {
+    fn output_subtitles(&mut self, subtitles: &[Subtitle]) {
+        let mut ass = String::from("[Script Info]\n");
+        ass.push_str(&format!("Title: {}\n", self.title));
+        ass.push_str("ScriptType: v4.00+\n");
+        ass.push_str("WrapStyle: 0\n");
+        ass.push_str("ScaledBorderAndShadow: yes\n");
+        ass.push_str(&format!("PlayResX: {}\n", self.play_res_x));
+        ass.push_str(&format!("PlayResY: {}\n", self.play_res_y));
+        ass.push('\n');
+
+        let mut styles = self.styles.clone();
+        if self.bilingual && !styles.iter().any(|style| style.name == ORIGINAL_STYLE) {
+            let mut original = styles.first().cloned().unwrap_or_default();
+            original.name = ORIGINAL_STYLE.to_string();
+            original.alignment = 8;
+            styles.push(original);
+        }
+
+        ass.push_str("[V4+ Styles]\n");
+        ass.push_str(&format!("Format: {}\n", STYLE_FORMAT));
+        for style in styles.iter() {
+            ass.push_str(&style.format());
+            ass.push('\n');
+        }
+        ass.push('\n');
+
+        let default_style = styles
+            .first()
+            .map_or("Default", |style| style.name.as_str());
+        // Lines of a style missing from the script are rendered with the default one
+        let line_style = |subtitle: &Subtitle| {
+            subtitle
+                .style
+                .as_deref()
+                .filter(|name| styles.iter().any(|style| style.name == *name))
+                .unwrap_or(default_style)
+        };
+        ass.push_str("[Events]\n");
+        ass.push_str(
+            "Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+        );
+        for subtitle in subtitles.iter() {
+            ass.push_str(&format!(
+                "Dialogue: 0,{},{},{},,0,0,0,,{}\n",
+                format_time(subtitle.start),
+                format_time(subtitle.end),
+                line_style(subtitle),
+                if self.karaoke && !subtitle.words.is_empty() {
+                    karaoke_text(subtitle)
+                } else {
+                    escape_text(&subtitle.text)
+                }
+            ));
+            if let Some(original_text) = subtitle.original_text.as_ref().filter(|_| self.bilingual)
+            {
+                ass.push_str(&format!(
+                    "Dialogue: 0,{},{},{},,0,0,0,,{}\n",
+                    format_time(subtitle.start),
+                    format_time(subtitle.end),
+                    ORIGINAL_STYLE,
+                    escape_text(original_text)
+                ));
+            }
+        }
+        self.file.write_all(ass.as_bytes()).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_subtitles() {
+        use std::io::Read;
+        use tempfile::TempDir;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let tmp_path = tmp_dir.path().join("test.ass");
+        let file = File::create(tmp_path).unwrap();
+        let mut exporter = AssSubtitleExporter::new(file);
+        let mut sign = AssStyle::default();
+        sign.apply_overrides("Name=Sign,Alignment=8,Italic=-1")
+            .unwrap();
+        exporter.styles.push(sign);
+        let subtitles = vec![
+            Subtitle {
+                start: 0.0,
+                end: 1.0,
+                text: "Hello,\nworld!".to_string(),
+                ..Default::default()
+            },
+            Subtitle {
+                start: 1.0,
+                end: 3723.5,
+                text: "{Goodbye}".to_string(),
+                style: Some("Sign".to_string()),
+                ..Default::default()
+            },
+            Subtitle {
+                start: 3723.5,
+                end: 3724.0,
+                text: "Bye".to_string(),
+                style: Some("Missing".to_string()),
+                ..Default::default()
+            },
+        ];
+        exporter.output_subtitles(&subtitles);
+
+        let tmp_path = tmp_dir.path().join("test.ass");
+        let mut file = File::open(tmp_path).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert!(contents.starts_with("[Script Info]\n"));
+        assert!(contents.contains(
+            "Style: Default,Arial,72,&H00FFFFFF,&H000000FF,&H00000000,&H80000000,\
+            0,0,0,0,100,100,0,0,1,3,1,2,40,40,60,1\n"
+        ));
+        assert!(contents.contains(
+            "Style: Sign,Arial,72,&H00FFFFFF,&H000000FF,&H00000000,&H80000000,\
+            0,-1,0,0,100,100,0,0,1,3,1,8,40,40,60,1\n"
+        ));
+        assert!(contents.ends_with(
+            "Dialogue: 0,0:00:00.00,0:00:01.00,Default,,0,0,0,,Hello,\\Nworld!\n\
+            Dialogue: 0,0:00:01.00,1:02:03.50,Sign,,0,0,0,,(Goodbye)\n\
+            Dialogue: 0,1:02:03.50,1:02:04.00,Default,,0,0,0,,Bye\n"
+        ));
+    }
+
+    #[test]
+    fn test_output_bilingual_subtitles() {
+        use std::io::Read;
+        use tempfile::TempDir;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let tmp_path = tmp_dir.path().join("test.ass");
+        let file = File::create(&tmp_path).unwrap();
+        let mut exporter = AssSubtitleExporter::new(file);
+        exporter.bilingual = true;
+        let subtitles = vec![Subtitle {
+            start: 0.0,
+            end: 1.0,
+            text: "Thank you".to_string(),
+            original_text: Some("ありがとう".to_string()),
+            ..Default::default()
+        }];
+        exporter.output_subtitles(&subtitles);
+
+        let mut contents = String::new();
+        File::open(tmp_path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        // The missing style is the default one at the top
+        assert!(contents.contains(
+            "Style: Original,Arial,72,&H00FFFFFF,&H000000FF,&H00000000,&H80000000,\
+            0,0,0,0,100,100,0,0,1,3,1,8,40,40,60,1\n"
+        ));
+        assert!(contents.ends_with(
+            "Dialogue: 0,0:00:00.00,0:00:01.00,Default,,0,0,0,,Thank you\n\
+            Dialogue: 0,0:00:00.00,0:00:01.00,Original,,0,0,0,,ありがとう\n"
+        ));
+    }
+
+    #[test]
+    fn test_karaoke_text() {
+        use crate::utils::Word;
+
+        let word = |start: f32, end: f32, text: &str| Word {
+            start,
+            end,
+            text: text.to_string(),
+            probability: 1.0,
+        };
+        let subtitle = Subtitle {
+            start: 1.0,
+            end: 3.0,
+            text: " And so my {fellow}".to_string(),
+            words: vec![
+                word(1.2, 1.5, " And"),
+                word(1.5, 1.8, " so"),
+                word(1.8, 2.0, " my"),
+                word(2.5, 3.0, " {fellow}"),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            karaoke_text(&subtitle),
+            "{\\k20}{\\k30}And{\\k30} so{\\k20} my{\\k50}{\\k50} (fellow)"
+        );
+    }
+
+    #[test]
+    fn test_parse_ass_styles() {
+        let content = "\u{feff}[Script Info]\n\
+            ScriptType: v4.00+\n\
+            \n\
+            [V4+ Styles]\n\
+            Format: Name, Fontname, Fontsize, PrimaryColour, Bold, Alignment, MarginV\n\
+            Style: Top,Noto Sans CJK JP,60,&H0000FFFF,-1,8,30\n\
+            \n\
+            [Events]\n\
+            Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n";
+        let styles = parse_ass_styles(content).unwrap();
+        assert_eq!(styles.len(), 1);
+        assert_eq!(styles[0].name, "Top");
+        assert_eq!(styles[0].fontname, "Noto Sans CJK JP");
+        assert_eq!(styles[0].fontsize, 60.0);
+        assert_eq!(styles[0].primary_colour, "&H0000FFFF");
+        assert!(styles[0].bold);
+        assert_eq!(styles[0].alignment, 8);
+        assert_eq!(styles[0].margin_v, 30);
+        // Unspecified fields keep the default
+        assert_eq!(styles[0].outline, 3.0);
+
+        assert!(parse_ass_styles("[V4+ Styles]\nStyle: Broken,Arial\n").is_err());
+        assert!(parse_ass_styles("[Events]\n").is_err());
+    }
+}
@@ -0,0 +1,520 @@
+use crate::output::ass::{AssStyle, AssSubtitleExporter};
+use crate::output::ffmpeg_subtitle::{map_streams, write_video, MuxStats};
+use crate::output::OutputSubtitles;
+use crate::output::Subtitle;
+use ffmpeg_next::{
+    self as ffmpeg, codec, decoder, encoder, filter, format, frame, media, picture, Dictionary,
+    Packet, Rational,
+};
+use tempfile::TempDir;
+
+/// Render the subtitles into the video frames (hardsubs), other streams are copied
+pub(crate) struct VideoSubtitleBurnInExporter {
+    in_video_path: String,
+    out_video_path: String,
+    /// Name of the video encoder, e.g. "libx264", "libx265", "libsvtav1"
+    pub codec: String,
+    pub crf: Option<u32>,
+    pub preset: Option<String>,
+    pub styles: Vec<AssStyle>,
+    /// Also render the original texts, see `AssSubtitleExporter::bilingual`
+    pub bilingual: bool,
+    /// Keep the input video at this path when it is replaced in place
+    pub backup_path: Option<String>,
+}
+
+impl VideoSubtitleBurnInExporter {
+    pub fn new(in_video_path: String, out_video_path: String) -> VideoSubtitleBurnInExporter {
+        VideoSubtitleBurnInExporter {
+            in_video_path,
+            out_video_path,
+            codec: "libx264".to_string(),
+            crf: Some(23),
+            preset: Some("medium".to_string()),
+            styles: vec![AssStyle::default()],
+            bilingual: false,
+            backup_path: None,
+        }
+    }
+
+    /// Re-encode the video with the subtitles rendered into its frames
+    pub fn output_video(&mut self, subtitles: &[Subtitle]) -> Result<(), String> {
+        // Write subtitles to a temp ASS file, rendered by the libass `ass` filter
+        let tmp_dir = TempDir::new().map_err(|e| e.to_string())?;
+        let tmp_path = tmp_dir.path().join("output.ass");
+        let tmp_path_str = tmp_path
+            .to_str()
+            .ok_or(format!("Invalid temp path {}", tmp_path.display()))?;
+
+        let file = std::fs::File::create(tmp_path_str).map_err(|e| e.to_string())?;
+        let mut exporter = AssSubtitleExporter::new(file);
+        exporter.styles = self.styles.clone();
+        exporter.bilingual = self.bilingual;
+        exporter.output_subtitles(subtitles);
+
+        let mut options = Dictionary::new();
+        if let Some(crf) = self.crf {
+            options.set("crf", &crf.to_string());
+        }
+        if let Some(preset) = &self.preset {
+            options.set("preset", preset);
+        }
+
+        burn_subtitle_into_video(
+            self.in_video_path.as_str(),
+            self.out_video_path.as_str(),
+            tmp_path_str,
+            self.codec.as_str(),
+            options,
+            self.backup_path.as_deref(),
+        )
+    }
+}
+
+impl OutputSubtitles for VideoSubtitleBurnInExporter {
+    fn output_subtitles(&mut self, subtitles: &[Subtitle]) {
+        if let Err(e) = self.output_video(subtitles) {
+            println!("Failed to burn subtitles: {}", e);
+        }
+    }
+}
+
+/// Escape a path to be used as a filter option value inside a filter graph description
+fn escape_filter_path(path: &str) -> String {
+    // Forward slashes are accepted on Windows as well, so only ':' and '\'' remain special
+    path.replace('\\', "/")
+        .replace(':', "\\\\:")
+        .replace('\'', "\\\\\\'")
+}
+
+fn subtitle_filter(
+    decoder: &decoder::Video,
+    time_base: Rational,
+    subtitle_path: &str,
+    pixel_format: format::Pixel,
+) -> Result<filter::Graph, ffmpeg::Error> {
+    let mut graph = filter::Graph::new();
+
+    let aspect_ratio = match decoder.aspect_ratio() {
+        Rational(0, _) => Rational(1, 1),
+        aspect_ratio => aspect_ratio,
+    };
+    let args = format!(
+        "video_size={}x{}:pix_fmt={}:time_base={}:pixel_aspect={}",
+        decoder.width(),
+        decoder.height(),
+        ffmpeg::ffi::AVPixelFormat::from(decoder.format()) as i32,
+        time_base,
+        aspect_ratio
+    );
+    graph.add(&filter::find("buffer").unwrap(), "in", &args)?;
+    graph.add(&filter::find("buffersink").unwrap(), "out", "")?;
+    graph.get("out").unwrap().set_pixel_format(pixel_format);
+
+    let spec = format!("ass=filename={}", escape_filter_path(subtitle_path));
+    graph.output("in", 0)?.input("out", 0)?.parse(&spec)?;
+    graph.validate()?;
+
+    Ok(graph)
+}
+
+struct BurnInTranscoder {
+    ost_index: usize,
+    /// Encoded packets written to the output
+    packets: usize,
+    decoder: decoder::Video,
+    graph: filter::Graph,
+    encoder: encoder::Video,
+    in_time_base: Rational,
+}
+
+impl BurnInTranscoder {
+    fn new(
+        ist: &format::stream::Stream,
+        octx: &mut format::context::Output,
+        subtitle_path: &str,
+        codec_name: &str,
+        options: Dictionary,
+    ) -> Result<Self, ffmpeg::Error> {
+        let global_header = octx.format().flags().contains(format::Flags::GLOBAL_HEADER);
+        let decoder = ffmpeg::codec::context::Context::from_parameters(ist.parameters())?
+            .decoder()
+            .video()?;
+
+        let codec = encoder::find_by_name(codec_name).ok_or(ffmpeg::Error::EncoderNotFound)?;
+        let mut ost = octx.add_stream(codec)?;
+        let ost_index = ost.index();
+        let mut encoder = codec::context::Context::new_with_codec(codec)
+            .encoder()
+            .video()?;
+        // 4:2:0 is the most widely playable format for the common encoders
+        let pixel_format = format::Pixel::YUV420P;
+        encoder.set_height(decoder.height());
+        encoder.set_width(decoder.width());
+        encoder.set_aspect_ratio(decoder.aspect_ratio());
+        encoder.set_format(pixel_format);
+        // A decoder opened from the parameters does not know the rate, the demuxer guesses it
+        let frame_rate = [ist.avg_frame_rate(), ist.rate()]
+            .into_iter()
+            .find(|rate| rate.numerator() > 0 && rate.denominator() > 0);
+        encoder.set_frame_rate(frame_rate);
+        encoder.set_time_base(ist.time_base());
+        if global_header {
+            encoder.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+
+        let encoder = encoder.open_with(options)?;
+        ost.set_parameters(&encoder);
+        let graph = subtitle_filter(&decoder, ist.time_base(), subtitle_path, pixel_format)?;
+
+        Ok(BurnInTranscoder {
+            ost_index,
+            packets: 0,
+            decoder,
+            graph,
+            encoder,
+            in_time_base: ist.time_base(),
+        })
+    }
+
+    fn receive_and_process_decoded_frames(
+        &mut self,
+        octx: &mut format::context::Output,
+        ost_time_base: Rational,
+    ) -> Result<(), ffmpeg::Error> {
+        let mut decoded = frame::Video::empty();
+        while self.decoder.receive_frame(&mut decoded).is_ok() {
+            decoded.set_pts(decoded.timestamp());
+            self.graph.get("in").unwrap().source().add(&decoded)?;
+            self.receive_and_process_filtered_frames(octx, ost_time_base)?;
+        }
+        Ok(())
+    }
+
+    fn receive_and_process_filtered_frames(
+        &mut self,
+        octx: &mut format::context::Output,
+        ost_time_base: Rational,
+    ) -> Result<(), ffmpeg::Error> {
+        let mut filtered = frame::Video::empty();
+        while self
+            .graph
+            .get("out")
+            .unwrap()
+            .sink()
+            .frame(&mut filtered)
+            .is_ok()
+        {
+            filtered.set_kind(picture::Type::None);
+            self.encoder.send_frame(&filtered)?;
+            self.receive_and_process_encoded_packets(octx, ost_time_base)?;
+        }
+        Ok(())
+    }
+
+    fn receive_and_process_encoded_packets(
+        &mut self,
+        octx: &mut format::context::Output,
+        ost_time_base: Rational,
+    ) -> Result<(), ffmpeg::Error> {
+        let mut encoded = Packet::empty();
+        while self.encoder.receive_packet(&mut encoded).is_ok() {
+            encoded.set_stream(self.ost_index);
+            encoded.rescale_ts(self.in_time_base, ost_time_base);
+            encoded.write_interleaved(octx)?;
+            self.packets += 1;
+        }
+        Ok(())
+    }
+
+    fn flush(
+        &mut self,
+        octx: &mut format::context::Output,
+        ost_time_base: Rational,
+    ) -> Result<(), ffmpeg::Error> {
+        self.decoder.send_eof()?;
+        self.receive_and_process_decoded_frames(octx, ost_time_base)?;
+        self.graph.get("in").unwrap().source().flush()?;
+        self.receive_and_process_filtered_frames(octx, ost_time_base)?;
+        self.encoder.send_eof()?;
+        self.receive_and_process_encoded_packets(octx, ost_time_base)
+    }
+}
+
+/// Re-encode the best video stream with the subtitles rendered, in place if both paths are the
+/// same file
+fn burn_subtitle_into_video(
+    in_video_path: &str,
+    out_video_path: &str,
+    subtitle_path: &str,
+    codec_name: &str,
+    options: Dictionary,
+    backup_path: Option<&str>,
+) -> Result<(), String> {
+    ffmpeg::init().unwrap();
+
+    write_video(in_video_path, out_video_path, backup_path, |output_file| {
+        transcode_with_subtitles(
+            in_video_path,
+            output_file,
+            subtitle_path,
+            codec_name,
+            options,
+        )
+    })
+}
+
+fn transcode_with_subtitles(
+    in_video_path: &str,
+    output_file: &str,
+    subtitle_path: &str,
+    codec_name: &str,
+    options: Dictionary,
+) -> Result<MuxStats, String> {
+    let mut ictx = format::input(&in_video_path)
+        .map_err(|e| format!("Failed to open {}: {}", in_video_path, e))?;
+    let mut octx = format::output(&output_file)
+        .map_err(|e| format!("Failed to create {}: {}", output_file, e))?;
+
+    let best_video_stream_index = ictx
+        .streams()
+        .best(media::Type::Video)
+        .map(|stream| stream.index())
+        .ok_or(format!("No video stream in {}", in_video_path))?;
+    // Audio and the other streams are copied untouched
+    let mut transcoder = None;
+    let mapping = map_streams(&ictx, &mut octx, false, |ist, octx| {
+        if ist.index() != best_video_stream_index {
+            return Ok(false);
+        }
+        let burn_in =
+            BurnInTranscoder::new(ist, octx, subtitle_path, codec_name, options.to_owned())
+                .map_err(|e| format!("Failed to set up the {} encoder: {}", codec_name, e))?;
+        transcoder = Some(burn_in);
+        Ok(true)
+    })?;
+    let mut transcoder = transcoder.ok_or(format!(
+        "Video stream {} cannot be written to {}",
+        best_video_stream_index, output_file
+    ))?;
+
+    octx.set_metadata(ictx.metadata().to_owned());
+    octx.write_header()
+        .map_err(|e| format!("Failed to write the header of {}: {}", output_file, e))?;
+
+    let mut stats = MuxStats {
+        streams: octx.nb_streams() as usize,
+        duration: Some(ictx.duration()).filter(|duration| *duration > 0),
+        ..Default::default()
+    };
+    let ost_time_bases: Vec<Rational> = octx.streams().map(|ost| ost.time_base()).collect();
+    for (stream, mut packet) in ictx.packets() {
+        let ist_index = stream.index();
+        let ost_index = match mapping.ost_indices[ist_index] {
+            Some(ost_index) => ost_index,
+            None => continue,
+        };
+        let ost_time_base = ost_time_bases[ost_index];
+        if ist_index == best_video_stream_index {
+            transcoder
+                .decoder
+                .send_packet(&packet)
+                .and_then(|_| {
+                    transcoder.receive_and_process_decoded_frames(&mut octx, ost_time_base)
+                })
+                .map_err(|e| format!("Failed to re-encode the video: {}", e))?;
+        } else {
+            packet.rescale_ts(mapping.ist_time_bases[ist_index], ost_time_base);
+            stats.count_packet(stream.parameters().medium());
+            packet.set_position(-1);
+            packet.set_stream(ost_index);
+            packet
+                .write_interleaved(&mut octx)
+                .map_err(|e| format!("Failed to write a packet of stream {}: {}", ist_index, e))?;
+        }
+    }
+    transcoder
+        .flush(&mut octx, ost_time_bases[transcoder.ost_index])
+        .map_err(|e| format!("Failed to re-encode the video: {}", e))?;
+
+    octx.write_trailer()
+        .map_err(|e| format!("Failed to write the trailer of {}: {}", output_file, e))?;
+    stats.packets += transcoder.packets;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::Path;
+
+    /// A second of 64x64 MPEG-4 video at 10 fps with a mono PCM audio stream, in Matroska
+    fn create_synthetic_clip(path: &str, wav_path: &str) {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(wav_path, spec).unwrap();
+        for i in 0..8000 {
+            writer
+                .write_sample(((i as f32 * 0.1).sin() * 8000.0) as i16)
+                .unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut wav = format::input(&wav_path).unwrap();
+        let mut octx = format::output(&path).unwrap();
+        let codec = encoder::find(codec::Id::MPEG4).unwrap();
+        let mut video = codec::context::Context::new_with_codec(codec)
+            .encoder()
+            .video()
+            .unwrap();
+        video.set_width(64);
+        video.set_height(64);
+        video.set_format(format::Pixel::YUV420P);
+        video.set_time_base(Rational(1, 10));
+        video.set_frame_rate(Some(Rational(10, 1)));
+        if octx.format().flags().contains(format::Flags::GLOBAL_HEADER) {
+            video.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+        let mut video = video.open_with(Dictionary::new()).unwrap();
+        let mut ost = octx.add_stream(codec).unwrap();
+        ost.set_parameters(&video);
+        ost.set_avg_frame_rate(Rational(10, 1));
+
+        let audio = wav.streams().best(media::Type::Audio).unwrap();
+        let audio_time_base = audio.time_base();
+        let mut audio_ost = octx.add_stream(encoder::find(codec::Id::None)).unwrap();
+        audio_ost.set_parameters(audio.parameters());
+        unsafe {
+            (*audio_ost.parameters().as_mut_ptr()).codec_tag = 0;
+        }
+        octx.write_header().unwrap();
+
+        let video_time_base = octx.stream(0).unwrap().time_base();
+        let write_encoded = |video: &mut encoder::Video, octx: &mut format::context::Output| {
+            let mut packet = Packet::empty();
+            while video.receive_packet(&mut packet).is_ok() {
+                packet.set_stream(0);
+                packet.rescale_ts(Rational(1, 10), video_time_base);
+                packet.write_interleaved(octx).unwrap();
+            }
+        };
+        let mut frame = frame::Video::new(format::Pixel::YUV420P, 64, 64);
+        for i in 0..10 {
+            // A gray level changing every frame
+            frame.data_mut(0).fill(16 + i as u8 * 20);
+            frame.data_mut(1).fill(128);
+            frame.data_mut(2).fill(128);
+            frame.set_pts(Some(i));
+            video.send_frame(&frame).unwrap();
+            write_encoded(&mut video, &mut octx);
+        }
+        video.send_eof().unwrap();
+        write_encoded(&mut video, &mut octx);
+
+        let audio_ost_time_base = octx.stream(1).unwrap().time_base();
+        for (_stream, mut packet) in wav.packets() {
+            packet.rescale_ts(audio_time_base, audio_ost_time_base);
+            packet.set_position(-1);
+            packet.set_stream(1);
+            packet.write_interleaved(&mut octx).unwrap();
+        }
+        octx.write_trailer().unwrap();
+    }
+
+    /// Data of the packets of the audio stream
+    fn audio_data(path: &str) -> Vec<u8> {
+        let mut ictx = format::input(&path).unwrap();
+        let index = ictx.streams().best(media::Type::Audio).unwrap().index();
+        ictx.packets()
+            .filter(|(stream, _)| stream.index() == index)
+            .flat_map(|(_, packet)| packet.data().unwrap_or_default().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn test_burn_subtitle_into_video() {
+        ffmpeg::init().unwrap();
+
+        let tmp_dir = TempDir::new().unwrap();
+        let path = |name: &str| tmp_dir.path().join(name).to_str().unwrap().to_string();
+        create_synthetic_clip(&path("input.mkv"), &path("input.wav"));
+        let mut exporter =
+            AssSubtitleExporter::new(std::fs::File::create(path("input.ass")).unwrap());
+        exporter.output_subtitles(&[Subtitle::new(0.0, 1.0, "Hello".to_string())]);
+
+        let burn = |output: &str, codec_name: &str, backup_path: Option<&str>| {
+            burn_subtitle_into_video(
+                &path("input.mkv"),
+                output,
+                &path("input.ass"),
+                codec_name,
+                Dictionary::new(),
+                backup_path,
+            )
+        };
+        burn(&path("output.mkv"), "ffv1", None).unwrap();
+
+        let input = format::input(&path("input.mkv")).unwrap();
+        let output = format::input(&path("output.mkv")).unwrap();
+        assert_eq!(output.nb_streams(), 2);
+        let video = output.streams().best(media::Type::Video).unwrap();
+        assert_eq!(video.parameters().id(), codec::Id::FFV1);
+        let audio = output.streams().best(media::Type::Audio).unwrap();
+        assert_eq!(audio.parameters().id(), codec::Id::PCM_S16LE);
+        assert_eq!(
+            audio_data(&path("output.mkv")),
+            audio_data(&path("input.mkv"))
+        );
+        assert!(
+            (output.duration() - input.duration()).abs() <= ffmpeg::ffi::AV_TIME_BASE as i64 / 10
+        );
+
+        // A bad encoder leaves no partial output behind
+        assert!(burn(&path("bad.mkv"), "not-an-encoder", None).is_err());
+        assert!(!Path::new(&path("bad.mkv")).exists());
+    }
+
+    #[test]
+    fn test_burn_subtitle_into_video_in_place() {
+        ffmpeg::init().unwrap();
+
+        let tmp_dir = TempDir::new().unwrap();
+        let path = |name: &str| tmp_dir.path().join(name).to_str().unwrap().to_string();
+        create_synthetic_clip(&path("video.mkv"), &path("video.wav"));
+        let original = std::fs::read(path("video.mkv")).unwrap();
+        let mut exporter =
+            AssSubtitleExporter::new(std::fs::File::create(path("video.ass")).unwrap());
+        exporter.output_subtitles(&[Subtitle::new(0.0, 1.0, "Hello".to_string())]);
+
+        burn_subtitle_into_video(
+            &path("video.mkv"),
+            &path("video.mkv"),
+            &path("video.ass"),
+            "ffv1",
+            Dictionary::new(),
+            Some(&path("video.mkv.bak")),
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(path("video.mkv.bak")).unwrap(), original);
+        let output = format::input(&path("video.mkv")).unwrap();
+        let video = output.streams().best(media::Type::Video).unwrap();
+        assert_eq!(video.parameters().id(), codec::Id::FFV1);
+        // No temp file is left, only the video, its backup, the WAV and the ASS subtitles
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 4);
+    }
+
+    #[test]
+    fn test_escape_filter_path() {
+        assert_eq!(escape_filter_path("/tmp/output.ass"), "/tmp/output.ass");
+        assert_eq!(
+            escape_filter_path("C:\\Users\\me\\output.ass"),
+            "C\\\\:/Users/me/output.ass"
+        );
+        assert_eq!(escape_filter_path("/tmp/it's.ass"), "/tmp/it\\\\\\'s.ass");
+    }
+}
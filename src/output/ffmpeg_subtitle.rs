@@ -1,17 +1,191 @@
 use crate::output::OutputSubtitles;
 use crate::output::Subtitle;
+use crate::utils::ffmpeg_stream::iso639_2_language;
+use crate::utils::time::TimeRange;
 use ffmpeg_next::{
     self as ffmpeg, codec, encoder,
-    ffi::{AVCodecID, AVMediaType},
-    format, media, Rational,
+    ffi::AVMediaType,
+    format::{self, stream::Disposition},
+    media, Dictionary, Packet, Rational,
 };
+use std::path::Path;
 use tempfile::TempDir;
 
+use super::ass::AssSubtitleExporter;
 use super::srt::SrtSubtitleExporter;
+use super::vtt::VttSubtitleExporter;
+
+/// A subtitle track to mux, with the metadata shown by players
+pub struct SubtitleTrack {
+    pub subtitles: Vec<Subtitle>,
+    /// ISO 639-2 language tag, e.g. "jpn"
+    pub language: Option<String>,
+    pub title: Option<String>,
+    /// Default and forced flags of the track
+    pub disposition: Disposition,
+}
+
+impl SubtitleTrack {
+    pub fn new(subtitles: Vec<Subtitle>) -> SubtitleTrack {
+        SubtitleTrack {
+            subtitles,
+            language: None,
+            title: None,
+            disposition: Disposition::empty(),
+        }
+    }
+
+    /// Tag the track with a ISO 639-1 ("ja") or ISO 639-2 ("jpn") language
+    pub fn with_language(mut self, language: &str) -> SubtitleTrack {
+        self.language = Some(iso639_2_language(language));
+        self
+    }
+
+    pub fn with_title(mut self, title: String) -> SubtitleTrack {
+        self.title = Some(title);
+        self
+    }
+
+    pub fn with_disposition(mut self, disposition: Disposition) -> SubtitleTrack {
+        self.disposition = disposition;
+        self
+    }
+
+    fn metadata(&self) -> Dictionary {
+        let mut metadata = Dictionary::new();
+        if let Some(language) = &self.language {
+            metadata.set("language", language);
+        }
+        if let Some(title) = &self.title {
+            metadata.set("title", title);
+        }
+        metadata
+    }
+}
+
+/// Text subtitle codec from its name, e.g. "srt" or "mov_text"
+pub(crate) fn parse_subtitle_codec(name: &str) -> Result<codec::Id, String> {
+    match name.to_lowercase().as_str() {
+        "srt" | "subrip" => Ok(codec::Id::SUBRIP),
+        "ass" | "ssa" => Ok(codec::Id::ASS),
+        "vtt" | "webvtt" => Ok(codec::Id::WEBVTT),
+        "mov_text" | "tx3g" => Ok(codec::Id::MOV_TEXT),
+        _ => Err(format!("Unsupported subtitle codec {}", name)),
+    }
+}
+
+/// Text subtitle codecs in order of preference for an output format
+fn preferred_subtitle_codecs(format_name: &str) -> &'static [codec::Id] {
+    match format_name {
+        "matroska" => &[codec::Id::SUBRIP, codec::Id::ASS],
+        "webm" => &[codec::Id::WEBVTT],
+        "mp4" | "mov" | "ipod" | "3gp" | "3g2" | "ismv" | "psp" => &[codec::Id::MOV_TEXT],
+        // Whichever the container accepts
+        _ => &[
+            codec::Id::SUBRIP,
+            codec::Id::MOV_TEXT,
+            codec::Id::WEBVTT,
+            codec::Id::ASS,
+        ],
+    }
+}
+
+fn format_supports_codec(format: &format::Output, codec: codec::Id) -> bool {
+    // 1 if supported, 0 if not and negative if unknown
+    unsafe { ffmpeg::ffi::avformat_query_codec(format.as_ptr(), codec.into(), 0) == 1 }
+}
+
+/// Whether a stream of the input can be copied to the output format
+fn format_supports_stream(format: &format::Output, medium: media::Type, codec: codec::Id) -> bool {
+    match medium {
+        // Audio and video streams are always copied
+        media::Type::Audio | media::Type::Video => true,
+        // Fonts and other files are only written by the Matroska muxer
+        media::Type::Attachment => format.name() == "matroska",
+        media::Type::Subtitle => format_supports_codec(format, codec),
+        // Matroska knows text data as subtitles, but only writes audio, video and subtitle tracks
+        media::Type::Data => format.name() != "matroska" && format_supports_codec(format, codec),
+        media::Type::Unknown => false,
+    }
+}
+
+/// Choose the subtitle codec of the output format, or check the requested one
+pub(crate) fn select_subtitle_codec(
+    format: &format::Output,
+    requested: Option<codec::Id>,
+) -> Result<codec::Id, String> {
+    match requested {
+        Some(codec) if format_supports_codec(format, codec) => Ok(codec),
+        Some(codec) => Err(format!(
+            "The {} container cannot carry {:?} subtitles",
+            format.name(),
+            codec
+        )),
+        None => preferred_subtitle_codecs(format.name())
+            .iter()
+            .copied()
+            .find(|codec| format_supports_codec(format, *codec))
+            .ok_or(format!(
+                "The {} container cannot carry text subtitles, use a .mkv, .webm, .mp4 or .mov output",
+                format.name()
+            )),
+    }
+}
+
+/// Write the subtitles to a file demuxed as the codec, timed for the trimmed video if trimming
+///
+/// mov_text has no file format, it is read from SRT and converted by `mov_text_packet`.
+fn write_subtitle_file(
+    subtitles: &[Subtitle],
+    path: &str,
+    codec: codec::Id,
+    trim_range: Option<&TimeRange>,
+) {
+    let mut subtitles = subtitles.to_vec();
+    if let Some(range) = trim_range {
+        // The trimmed video starts at the range start
+        subtitles = range.clip_subtitles(subtitles);
+        subtitles.iter_mut().for_each(|s| {
+            s.start -= range.start;
+            s.end -= range.start;
+        });
+    }
+    let file = std::fs::File::create(path).unwrap();
+    match codec {
+        codec::Id::ASS => AssSubtitleExporter::new(file).output_subtitles(&subtitles),
+        codec::Id::WEBVTT => VttSubtitleExporter::new(file).output_subtitles(&subtitles),
+        _ => SrtSubtitleExporter::new(file).output_subtitles(&subtitles),
+    }
+}
+
+fn subtitle_file_extension(codec: codec::Id) -> &'static str {
+    match codec {
+        codec::Id::ASS => "ass",
+        codec::Id::WEBVTT => "vtt",
+        _ => "srt",
+    }
+}
+
+/// mov_text samples are the text prefixed by its length in bytes
+fn mov_text_packet(packet: &Packet) -> Packet {
+    let text = packet.data().unwrap_or(&[]);
+    let mut data = Vec::with_capacity(text.len() + 2);
+    data.extend_from_slice(&(text.len() as u16).to_be_bytes());
+    data.extend_from_slice(text);
+
+    let mut mov_text = Packet::copy(&data);
+    mov_text.set_pts(packet.pts());
+    mov_text.set_dts(packet.dts());
+    mov_text.set_duration(packet.duration());
+    mov_text
+}
 
 pub(crate) struct VideoSubtitleTrackExporter {
     in_video_path: String,
     out_video_path: String,
+    trim_range: Option<TimeRange>,
+    subtitle_codec: Option<codec::Id>,
+    backup_path: Option<String>,
 }
 
 impl VideoSubtitleTrackExporter {
@@ -19,123 +193,845 @@ impl VideoSubtitleTrackExporter {
         VideoSubtitleTrackExporter {
             in_video_path,
             out_video_path,
+            trim_range: None,
+            subtitle_codec: None,
+            backup_path: None,
         }
     }
-}
 
-impl OutputSubtitles for VideoSubtitleTrackExporter {
-    fn output_subtitles(&mut self, subtitles: &[Subtitle]) {
-        // Write subtitles to a temp SRT file
-        let tmp_dir = TempDir::new().unwrap();
-        let tmp_path = tmp_dir.path().join("output.srt");
-        let tmp_path_str = tmp_path.as_os_str().to_str().unwrap();
+    /// Only keep the given part of the video in the output (the full video is kept by default)
+    pub fn with_trim_range(mut self, range: TimeRange) -> VideoSubtitleTrackExporter {
+        self.trim_range = Some(range);
+        self
+    }
 
-        let file = std::fs::File::create(tmp_path_str).unwrap();
-        let mut exporter = SrtSubtitleExporter::new(file);
-        exporter.output_subtitles(&subtitles);
+    /// Use this codec instead of the one preferred by the output container
+    pub fn with_subtitle_codec(mut self, codec: codec::Id) -> VideoSubtitleTrackExporter {
+        self.subtitle_codec = Some(codec);
+        self
+    }
 
-        // Export subtitles to the video
+    /// Keep the input video at this path when it is replaced in place
+    pub fn with_backup(mut self, backup_path: String) -> VideoSubtitleTrackExporter {
+        self.backup_path = Some(backup_path);
+        self
+    }
+
+    /// Mux the tracks after the streams of the input video, in the given order
+    pub fn output_tracks(&mut self, tracks: &[SubtitleTrack]) -> Result<(), String> {
         export_subtitle_to_video(
             self.in_video_path.as_str(),
             self.out_video_path.as_str(),
-            tmp_path_str,
-        );
+            tracks,
+            self.trim_range.as_ref(),
+            self.subtitle_codec,
+            self.backup_path.as_deref(),
+        )
     }
 }
 
-fn export_subtitle_to_video(in_video_path: &str, out_video_path: &str, subtitle_path: &str) {
-    ffmpeg::init().unwrap();
+impl OutputSubtitles for VideoSubtitleTrackExporter {
+    fn output_subtitles(&mut self, subtitles: &[Subtitle]) {
+        if let Err(e) = self.output_tracks(&[SubtitleTrack::new(subtitles.to_vec())]) {
+            println!("Failed to mux subtitles: {}", e);
+        }
+    }
+}
+
+/// Whether both paths are the same file, e.g. "video.mp4" and "./video.mp4"
+pub(crate) fn is_same_file(a: &str, b: &str) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Output stream index and time base of each input stream, the index is `None` if dropped
+pub(crate) struct StreamMapping {
+    pub ost_indices: Vec<Option<usize>>,
+    pub ist_time_bases: Vec<Rational>,
+}
+
+/// Add an output stream for each input stream supported by the output container, with the
+/// metadata and disposition of the input stream
+///
+/// `add_stream` returns `true` if it added the output stream itself (e.g. to re-encode it),
+/// otherwise the stream is copied as is.
+pub(crate) fn map_streams(
+    ictx: &format::context::Input,
+    octx: &mut format::context::Output,
+    remove_subtitle_default: bool,
+    mut add_stream: impl FnMut(
+        &format::stream::Stream,
+        &mut format::context::Output,
+    ) -> Result<bool, String>,
+) -> Result<StreamMapping, String> {
+    let mut mapping = StreamMapping {
+        ost_indices: vec![None; ictx.nb_streams() as _],
+        ist_time_bases: vec![Rational(0, 1); ictx.nb_streams() as _],
+    };
+    for (ist_index, ist) in ictx.streams().enumerate() {
+        let ist_medium = ist.parameters().medium();
+        println!("ist_index: {}, ist_type: {:?}", ist_index, ist_medium);
+        if !format_supports_stream(&octx.format(), ist_medium, ist.parameters().id()) {
+            println!(
+                "Skipping stream {} ({:?} {:?}), not supported by the {} container",
+                ist_index,
+                ist_medium,
+                ist.parameters().id(),
+                octx.format().name()
+            );
+            continue;
+        }
+        let ost_index = octx.nb_streams() as usize;
+        if !add_stream(&ist, octx)? {
+            let mut ost = octx
+                .add_stream(encoder::find(codec::Id::None))
+                .map_err(|e| format!("Failed to add stream {}: {}", ist_index, e))?;
+            ost.set_parameters(ist.parameters());
+            // We need to set codec_tag to 0 lest we run into incompatible codec tag
+            // issues when muxing into a different container format. Unfortunately
+            // there's no high level API to do this (yet).
+            unsafe {
+                (*ost.parameters().as_mut_ptr()).codec_tag = 0;
+            }
+        }
+        let mut ost = octx
+            .stream_mut(ost_index)
+            .ok_or(format!("No output stream for stream {}", ist_index))?;
+        // Language, title, attachment file name and mime type...
+        ost.set_metadata(ist.metadata().to_owned());
+        let mut disposition = ist.disposition();
+        if ist_medium == media::Type::Subtitle && remove_subtitle_default {
+            disposition.remove(Disposition::DEFAULT);
+        }
+        unsafe {
+            (*ost.as_mut_ptr()).disposition = disposition.bits();
+        }
+        mapping.ost_indices[ist_index] = Some(ost_index);
+        mapping.ist_time_bases[ist_index] = ist.time_base();
+    }
+    Ok(mapping)
+}
+
+/// Temp output next to the video replaced in place, renamed over it once written
+fn in_place_output_path(in_video_path: &str) -> String {
+    // Keep the extension, the output format is guessed from it
+    let extension = Path::new(in_video_path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("mp4");
+    format!("{}.tmp.{}", in_video_path, extension)
+}
+
+/// Streams and packets written to the output, checked once it is read back
+#[derive(Debug, Default)]
+pub(crate) struct MuxStats {
+    pub streams: usize,
+    /// Packets of the audio, video and data streams
+    pub packets: usize,
+    /// Packets of the subtitle streams, muxers like mp4 add empty ones to clear the screen
+    pub subtitle_packets: usize,
+    /// Duration of the input in `AV_TIME_BASE` units, `None` if unknown or trimmed
+    pub duration: Option<i64>,
+}
+
+impl MuxStats {
+    /// Count a packet written to (or read from) a stream of the given type
+    pub fn count_packet(&mut self, medium: media::Type) {
+        if medium == media::Type::Subtitle {
+            self.subtitle_packets += 1;
+        } else {
+            self.packets += 1;
+        }
+    }
+}
 
-    let in_place = in_video_path == out_video_path;
+/// Read the muxed file back, so a broken output never replaces the input
+fn verify_output(output_file: &str, expected: &MuxStats) -> Result<(), String> {
+    let mut ictx = format::input(&output_file)
+        .map_err(|e| format!("Failed to read back {}: {}", output_file, e))?;
+    let mut stats = MuxStats {
+        streams: ictx.nb_streams() as usize,
+        duration: Some(ictx.duration()).filter(|duration| *duration > 0),
+        ..Default::default()
+    };
+    for (stream, _packet) in ictx.packets() {
+        stats.count_packet(stream.parameters().medium());
+    }
+
+    let truncated = match (expected.duration, stats.duration) {
+        // A second of tolerance for the rounding of the muxer
+        (Some(expected), Some(duration)) => {
+            duration + (ffmpeg::ffi::AV_TIME_BASE as i64) < expected
+        }
+        (Some(_), None) => true,
+        _ => false,
+    };
+    if stats.streams != expected.streams
+        || stats.packets != expected.packets
+        || stats.subtitle_packets < expected.subtitle_packets
+        || truncated
+    {
+        return Err(format!(
+            "{} does not match what was written, expected {:?} but read {:?}",
+            output_file, expected, stats
+        ));
+    }
+    Ok(())
+}
+
+/// Keep a copy of the file before it is replaced, as a hard link if possible
+fn backup_file(path: &str, backup_path: &str) -> Result<(), String> {
+    std::fs::hard_link(path, backup_path)
+        .or_else(|_| std::fs::copy(path, backup_path).map(|_| ()))
+        .map_err(|e| format!("Failed to back up {} to {}: {}", path, backup_path, e))
+}
+
+/// Write the output video with `write`, in place if both paths are the same file
+///
+/// In place, the output is written next to the input, read back and verified, then atomically
+/// renamed over the input. Nothing is left behind on failure and the input is untouched.
+pub(crate) fn write_video(
+    in_video_path: &str,
+    out_video_path: &str,
+    backup_path: Option<&str>,
+    write: impl FnOnce(&str) -> Result<MuxStats, String>,
+) -> Result<(), String> {
+    let in_place = is_same_file(in_video_path, out_video_path);
+    if let Some(backup_path) = backup_path.filter(|path| in_place && Path::new(path).exists()) {
+        return Err(format!("The backup {} already exists", backup_path));
+    }
     let output_file = if in_place {
-        format!("{}.tmp", in_video_path)
+        in_place_output_path(in_video_path)
     } else {
         out_video_path.to_string()
     };
 
-    let mut ictx = format::input(&in_video_path).unwrap();
-    let mut octx = format::output(&output_file).unwrap();
+    let result = write(&output_file)
+        .and_then(|stats| {
+            if in_place {
+                verify_output(&output_file, &stats)
+            } else {
+                Ok(())
+            }
+        })
+        .and_then(|_| match backup_path {
+            Some(backup_path) if in_place => backup_file(in_video_path, backup_path),
+            _ => Ok(()),
+        });
+    if let Err(e) = result {
+        std::fs::remove_file(&output_file).ok();
+        return Err(e);
+    }
 
-    let mut stream_mapping = vec![0; ictx.nb_streams() as _];
-    let mut ist_time_bases = vec![Rational(0, 1); ictx.nb_streams() as _];
-    let mut ost_index = 0;
-    let _stream_count = ictx.nb_streams();
-    for (ist_index, ist) in ictx.streams().enumerate() {
-        let ist_medium = ist.parameters().medium();
-        println!(
-            "ist_index: {}, ist_type: {:?}",
-            ist_index,
-            ist.parameters().medium()
-        );
-        if ist_medium != media::Type::Audio
-            && ist_medium != media::Type::Video
-            && ist_medium != media::Type::Subtitle
-        {
-            // Skip non-audio, non-video, non-subtitle streams
-            // Note: we might lose Data/Attachment streams here
-            stream_mapping[ist_index] = -1;
-            continue;
+    if in_place {
+        // Atomic as the output is on the same file system
+        std::fs::rename(&output_file, in_video_path)
+            .map_err(|e| format!("Failed to replace {}: {}", in_video_path, e))?;
+        println!("Replaced {} in place", in_video_path);
+    }
+    Ok(())
+}
+
+/// Mux the subtitles, in place if both paths are the same file
+fn export_subtitle_to_video(
+    in_video_path: &str,
+    out_video_path: &str,
+    tracks: &[SubtitleTrack],
+    trim_range: Option<&TimeRange>,
+    subtitle_codec: Option<codec::Id>,
+    backup_path: Option<&str>,
+) -> Result<(), String> {
+    ffmpeg::init().unwrap();
+
+    write_video(in_video_path, out_video_path, backup_path, |output_file| {
+        mux_subtitles_to_video(
+            in_video_path,
+            output_file,
+            tracks,
+            trim_range,
+            subtitle_codec,
+        )
+    })
+}
+
+fn mux_subtitles_to_video(
+    in_video_path: &str,
+    output_file: &str,
+    tracks: &[SubtitleTrack],
+    trim_range: Option<&TimeRange>,
+    subtitle_codec: Option<codec::Id>,
+) -> Result<MuxStats, String> {
+    // A track without subtitles (e.g. the original text of whisper translations) is skipped
+    let tracks: Vec<&SubtitleTrack> = tracks
+        .iter()
+        .filter(|track| !track.subtitles.is_empty())
+        .collect();
+    if tracks.is_empty() {
+        return Err("No subtitle to mux".to_string());
+    }
+
+    let mut ictx = format::input(&in_video_path)
+        .map_err(|e| format!("Failed to open {}: {}", in_video_path, e))?;
+    let mut octx = format::output(&output_file)
+        .map_err(|e| format!("Failed to create {}: {}", output_file, e))?;
+    let subtitle_codec = select_subtitle_codec(&octx.format(), subtitle_codec)?;
+    println!("Muxing subtitles as {:?}", subtitle_codec);
+
+    // Write subtitles to temp files, one per track
+    let tmp_dir = TempDir::new().map_err(|e| e.to_string())?;
+    let subtitle_paths: Vec<String> = tracks
+        .iter()
+        .enumerate()
+        .map(|(i, track)| {
+            let tmp_path = tmp_dir.path().join(format!(
+                "output.{}.{}",
+                i,
+                subtitle_file_extension(subtitle_codec)
+            ));
+            let tmp_path_str = tmp_path.as_os_str().to_str().unwrap();
+            write_subtitle_file(&track.subtitles, tmp_path_str, subtitle_codec, trim_range);
+            tmp_path_str.to_string()
+        })
+        .collect();
+
+    // The added default track replaces the default subtitles of the input
+    let has_default_track = tracks
+        .iter()
+        .any(|track| track.disposition.contains(Disposition::DEFAULT));
+    let mapping = map_streams(&ictx, &mut octx, has_default_track, |_, _| Ok(false))?;
+
+    // Chapters, shifted to the start of the trimmed video
+    for chapter in ictx.chapters() {
+        let time_base = chapter.time_base();
+        let (mut start, mut end) = (chapter.start(), chapter.end());
+        if let Some(range) = trim_range {
+            let offset = (range.start as f64 / f64::from(time_base)) as i64;
+            let limit = range
+                .end
+                .map(|end| (end as f64 / f64::from(time_base)) as i64);
+            if end <= offset || limit.is_some_and(|limit| start >= limit) {
+                continue;
+            }
+            start = (start - offset).max(0);
+            end = limit.map_or(end, |limit| end.min(limit)) - offset;
         }
-        stream_mapping[ist_index] = ost_index;
-        ist_time_bases[ist_index] = ist.time_base();
-        ost_index += 1;
-        let mut ost = octx.add_stream(encoder::find(codec::Id::None)).unwrap();
-        ost.set_parameters(ist.parameters());
-        // We need to set codec_tag to 0 lest we run into incompatible codec tag
-        // issues when muxing into a different container format. Unfortunately
-        // there's no high level API to do this (yet).
+        let metadata = chapter.metadata();
+        let mut ost_chapter = octx
+            .add_chapter(
+                chapter.id(),
+                time_base,
+                start,
+                end,
+                metadata.get("title").unwrap_or(""),
+            )
+            .map_err(|e| format!("Failed to copy chapter {}: {}", chapter.id(), e))?;
+        for (key, value) in metadata.iter().filter(|(key, _)| *key != "title") {
+            ost_chapter.set_metadata(key, value);
+        }
+    }
+
+    // Add subtitle tracks
+    let mut subtitle_ictxs = Vec::with_capacity(tracks.len());
+    for (subtitle_path, track) in subtitle_paths.iter().zip(tracks) {
+        let subtitle_ictx = format::input(subtitle_path)
+            .map_err(|e| format!("Failed to read the subtitles: {}", e))?;
+        let subtitle_stream = subtitle_ictx
+            .streams()
+            .best(media::Type::Subtitle)
+            .ok_or("No subtitle to mux".to_string())?;
+        let subtitle_time_base = subtitle_stream.time_base();
+        let mut subtitle_ost = octx
+            .add_stream(encoder::find(subtitle_codec))
+            .map_err(|e| format!("Failed to add the subtitle stream: {}", e))?;
+        let subtitle_stream_parameters = subtitle_stream.parameters().clone();
+        subtitle_ost.set_parameters(subtitle_stream_parameters);
+        subtitle_ost.set_metadata(track.metadata());
         unsafe {
-            (*ost.parameters().as_mut_ptr()).codec_tag = 0;
-        }
-    }
-
-    // Add subtitle track
-    let mut subtitle_ictx = format::input(&subtitle_path).unwrap();
-    let subtitle_stream = subtitle_ictx.streams().best(media::Type::Subtitle).unwrap();
-    let mut subtitle_ost = octx.add_stream(encoder::find(codec::Id::MOV_TEXT)).unwrap();
-    let subtitle_stream_parameters = subtitle_stream.parameters().clone();
-    subtitle_ost.set_parameters(subtitle_stream_parameters);
-    unsafe {
-        // TODO: Support more subtitle codecs with container formats that support them
-        (*subtitle_ost.parameters().as_mut_ptr()).codec_type = AVMediaType::AVMEDIA_TYPE_SUBTITLE;
-        (*subtitle_ost.parameters().as_mut_ptr()).codec_id = AVCodecID::AV_CODEC_ID_MOV_TEXT;
-        (*subtitle_ost.parameters().as_mut_ptr()).codec_tag = 0;
-    }
-    println!(
-        "subtitle_ost: {:?} {:?}",
-        subtitle_ost.parameters().medium(),
-        subtitle_ost.parameters().id()
-    );
+            (*subtitle_ost.as_mut_ptr()).disposition = track.disposition.bits();
+            (*subtitle_ost.parameters().as_mut_ptr()).codec_type =
+                AVMediaType::AVMEDIA_TYPE_SUBTITLE;
+            (*subtitle_ost.parameters().as_mut_ptr()).codec_id = subtitle_codec.into();
+            (*subtitle_ost.parameters().as_mut_ptr()).codec_tag = 0;
+        }
+        println!(
+            "subtitle_ost: {:?} {:?}",
+            subtitle_ost.parameters().medium(),
+            subtitle_ost.parameters().id()
+        );
+        subtitle_ictxs.push((subtitle_ost.index(), subtitle_time_base, subtitle_ictx));
+    }
     octx.set_metadata(ictx.metadata().to_owned());
     println!("metadata: {:?}", ictx.metadata());
-    octx.write_header().unwrap();
+    octx.write_header()
+        .map_err(|e| format!("Failed to write the header of {}: {}", output_file, e))?;
+
+    let mut stats = MuxStats {
+        streams: octx.nb_streams() as usize,
+        duration: Some(ictx.duration()).filter(|duration| *duration > 0 && trim_range.is_none()),
+        ..Default::default()
+    };
+
+    if let Some(range) = trim_range.filter(|range| range.start > 0.0) {
+        // Seek to the keyframe before the start, as packets are copied without re-encoding
+        let timestamp = (range.start as f64 * ffmpeg::ffi::AV_TIME_BASE as f64) as i64;
+        ictx.seek(timestamp, ..timestamp)
+            .map_err(|e| format!("Failed to seek to {}s: {}", range.start, e))?;
+    }
 
     for (stream, mut packet) in ictx.packets() {
         let ist_index = stream.index();
-        let ost_index = stream_mapping[ist_index];
-        if ost_index < 0 {
-            continue;
+        let ost_index = match mapping.ost_indices[ist_index] {
+            Some(ost_index) => ost_index,
+            None => continue,
+        };
+        if let Some(range) = trim_range {
+            let time_base = f64::from(mapping.ist_time_bases[ist_index]);
+            let pts = packet.pts().or(packet.dts()).unwrap_or(0) as f64 * time_base;
+            if range.end.is_some_and(|end| pts > end as f64) {
+                continue;
+            }
+            // The seek lands before the start, video packets are kept to decode the first frames
+            let end = pts + packet.duration() as f64 * time_base;
+            if end <= range.start as f64 && stream.parameters().medium() != media::Type::Video {
+                continue;
+            }
+            // Shift to the start of the range, what is left before it is shown at the start
+            let offset = (range.start as f64 / time_base) as i64;
+            packet.set_pts(packet.pts().map(|pts| (pts - offset).max(0)));
+            packet.set_dts(packet.dts().map(|dts| (dts - offset).max(0)));
         }
-        let ost = octx.stream(ost_index as _).unwrap();
-        packet.rescale_ts(ist_time_bases[ist_index], ost.time_base());
+        let ost = octx.stream(ost_index).unwrap();
+        packet.rescale_ts(mapping.ist_time_bases[ist_index], ost.time_base());
+        stats.count_packet(ost.parameters().medium());
         packet.set_position(-1);
-        packet.set_stream(ost_index as _);
-        packet.write_interleaved(&mut octx).unwrap();
+        packet.set_stream(ost_index);
+        packet
+            .write_interleaved(&mut octx)
+            .map_err(|e| format!("Failed to write a packet of stream {}: {}", ist_index, e))?;
     }
 
-    for (_stream, mut packet) in subtitle_ictx.packets() {
-        packet.set_stream((octx.nb_streams() - 1) as usize);
-        packet.write_interleaved(&mut octx).unwrap();
+    for (subtitle_ost_index, subtitle_time_base, subtitle_ictx) in subtitle_ictxs.iter_mut() {
+        let ost_time_base = octx.stream(*subtitle_ost_index).unwrap().time_base();
+        for (_stream, mut packet) in subtitle_ictx.packets() {
+            if subtitle_codec == codec::Id::MOV_TEXT {
+                packet = mov_text_packet(&packet);
+            }
+            packet.rescale_ts(*subtitle_time_base, ost_time_base);
+            packet.set_stream(*subtitle_ost_index);
+            packet
+                .write_interleaved(&mut octx)
+                .map_err(|e| format!("Failed to write a subtitle packet: {}", e))?;
+            stats.subtitle_packets += 1;
+        }
     }
 
-    octx.write_trailer().unwrap();
-
-    if in_place {
-        std::fs::rename(&output_file, out_video_path).unwrap();
-    }
+    octx.write_trailer()
+        .map_err(|e| format!("Failed to write the trailer of {}: {}", output_file, e))?;
+    Ok(stats)
 }
 
 #[cfg(test)]
 mod tests {
-    // TODO: Add tests
+    use super::*;
+
+    #[test]
+    fn test_subtitle_track_metadata() {
+        let track = SubtitleTrack::new(Vec::new())
+            .with_language("ja")
+            .with_title("Japanese (original)".to_string())
+            .with_disposition(Disposition::DEFAULT | Disposition::FORCED);
+        assert_eq!(track.language.as_deref(), Some("jpn"));
+        let metadata = track.metadata();
+        assert_eq!(metadata.get("language"), Some("jpn"));
+        assert_eq!(metadata.get("title"), Some("Japanese (original)"));
+        assert!(track.disposition.contains(Disposition::FORCED));
+
+        assert!(SubtitleTrack::new(Vec::new())
+            .metadata()
+            .get("language")
+            .is_none());
+    }
+
+    #[test]
+    fn test_select_subtitle_codec() {
+        ffmpeg::init().unwrap();
+
+        let tmp_dir = TempDir::new().unwrap();
+        let select = |name: &str, requested: Option<codec::Id>| {
+            let octx = format::output(&tmp_dir.path().join(name)).unwrap();
+            select_subtitle_codec(&octx.format(), requested)
+        };
+        assert_eq!(select("video.mkv", None), Ok(codec::Id::SUBRIP));
+        assert_eq!(
+            select("video.mkv", Some(codec::Id::ASS)),
+            Ok(codec::Id::ASS)
+        );
+        assert_eq!(select("video.webm", None), Ok(codec::Id::WEBVTT));
+        assert_eq!(select("video.mp4", None), Ok(codec::Id::MOV_TEXT));
+        assert_eq!(select("video.mov", None), Ok(codec::Id::MOV_TEXT));
+        assert!(select("video.mp4", Some(codec::Id::SUBRIP)).is_err());
+        assert!(select("video.webm", Some(codec::Id::MOV_TEXT)).is_err());
+        assert!(select("video.avi", None).is_err());
+
+        assert_eq!(parse_subtitle_codec("SRT"), Ok(codec::Id::SUBRIP));
+        assert!(parse_subtitle_codec("pgs").is_err());
+    }
+
+    const FONT: &[u8] = b"not really a font";
+
+    /// A Matroska file with a forced subtitle track, a font attachment and two chapters
+    fn create_synthetic_mkv(mkv_path: &str, srt_path: &str) {
+        std::fs::write(
+            srt_path,
+            "1\n00:00:00,000 --> 00:00:01,000\nこんにちは\n\n\
+            2\n00:00:02,000 --> 00:00:03,000\nさようなら\n\n",
+        )
+        .unwrap();
+        let mut ictx = format::input(&srt_path).unwrap();
+        let mut octx = format::output(&mkv_path).unwrap();
+
+        let ist = ictx.streams().best(media::Type::Subtitle).unwrap();
+        let ist_time_base = ist.time_base();
+        let mut ost = octx.add_stream(encoder::find(codec::Id::None)).unwrap();
+        ost.set_parameters(ist.parameters());
+        let mut metadata = Dictionary::new();
+        metadata.set("language", "jpn");
+        metadata.set("title", "Signs");
+        ost.set_metadata(metadata);
+        unsafe {
+            (*ost.as_mut_ptr()).disposition = (Disposition::DEFAULT | Disposition::FORCED).bits();
+        }
+
+        let mut attachment = octx.add_stream(encoder::find(codec::Id::None)).unwrap();
+        unsafe {
+            let parameters = (*attachment.as_mut_ptr()).codecpar;
+            (*parameters).codec_type = AVMediaType::AVMEDIA_TYPE_ATTACHMENT;
+            (*parameters).codec_id = codec::Id::TTF.into();
+            // Freed by ffmpeg with the parameters, so allocated by it with the padding
+            let extradata = ffmpeg::ffi::av_mallocz(
+                FONT.len() + ffmpeg::ffi::AV_INPUT_BUFFER_PADDING_SIZE as usize,
+            ) as *mut u8;
+            std::ptr::copy_nonoverlapping(FONT.as_ptr(), extradata, FONT.len());
+            (*parameters).extradata = extradata;
+            (*parameters).extradata_size = FONT.len() as i32;
+        }
+        let mut metadata = Dictionary::new();
+        metadata.set("filename", "font.ttf");
+        metadata.set("mimetype", "font/ttf");
+        attachment.set_metadata(metadata);
+
+        octx.add_chapter(1, Rational(1, 1000), 0, 2000, "Opening")
+            .unwrap();
+        octx.add_chapter(2, Rational(1, 1000), 2000, 3000, "Ending")
+            .unwrap();
+        let mut metadata = Dictionary::new();
+        metadata.set("title", "Synthetic");
+        octx.set_metadata(metadata);
+        octx.write_header().unwrap();
+
+        for (_stream, mut packet) in ictx.packets() {
+            packet.rescale_ts(ist_time_base, octx.stream(0).unwrap().time_base());
+            packet.set_stream(0);
+            packet.write_interleaved(&mut octx).unwrap();
+        }
+        octx.write_trailer().unwrap();
+    }
+
+    /// A NUT file with a text data stream, Matroska cannot carry data streams
+    fn create_synthetic_data_stream(path: &str) {
+        let mut octx = format::output(&path).unwrap();
+        let mut ost = octx.add_stream(encoder::find(codec::Id::None)).unwrap();
+        ost.set_time_base(Rational(1, 1000));
+        unsafe {
+            let parameters = (*ost.as_mut_ptr()).codecpar;
+            (*parameters).codec_type = AVMediaType::AVMEDIA_TYPE_DATA;
+            (*parameters).codec_id = codec::Id::TEXT.into();
+        }
+        octx.write_header().unwrap();
+
+        let ost_time_base = octx.stream(0).unwrap().time_base();
+        for pts in [0, 1000] {
+            let mut packet = Packet::copy(b"data");
+            packet.set_pts(Some(pts));
+            packet.set_dts(Some(pts));
+            packet.set_duration(1000);
+            packet.rescale_ts(Rational(1, 1000), ost_time_base);
+            packet.set_stream(0);
+            packet.write_interleaved(&mut octx).unwrap();
+        }
+        octx.write_trailer().unwrap();
+    }
+
+    #[test]
+    fn test_map_data_stream() {
+        ffmpeg::init().unwrap();
+
+        let tmp_dir = TempDir::new().unwrap();
+        let path = |name: &str| tmp_dir.path().join(name).to_str().unwrap().to_string();
+        create_synthetic_data_stream(&path("input.nut"));
+        let ictx = format::input(&path("input.nut")).unwrap();
+        assert_eq!(
+            ictx.stream(0).unwrap().parameters().medium(),
+            media::Type::Data
+        );
+
+        // Copied by a muxer accepting the codec
+        let mut octx = format::output(&path("output.nut")).unwrap();
+        let mapping = map_streams(&ictx, &mut octx, false, |_, _| Ok(false)).unwrap();
+        assert_eq!(mapping.ost_indices, vec![Some(0)]);
+        assert_eq!(octx.stream(0).unwrap().parameters().id(), codec::Id::TEXT);
+
+        // Skipped with a warning otherwise
+        let mut octx = format::output(&path("output.mkv")).unwrap();
+        let mapping = map_streams(&ictx, &mut octx, false, |_, _| Ok(false)).unwrap();
+        assert_eq!(mapping.ost_indices, vec![None]);
+        assert_eq!(octx.nb_streams(), 0);
+
+        // The packets of the skipped stream are dropped when muxing
+        let track = SubtitleTrack::new(vec![Subtitle::new(0.0, 1.0, "Hello".to_string())]);
+        export_subtitle_to_video(
+            &path("input.nut"),
+            &path("output.mkv"),
+            &[track],
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        let ictx = format::input(&path("output.mkv")).unwrap();
+        assert_eq!(ictx.nb_streams(), 1);
+        assert_eq!(
+            ictx.stream(0).unwrap().parameters().medium(),
+            media::Type::Subtitle
+        );
+    }
+
+    #[test]
+    fn test_export_keeps_attachments_and_chapters() {
+        ffmpeg::init().unwrap();
+
+        let tmp_dir = TempDir::new().unwrap();
+        let path = |name: &str| tmp_dir.path().join(name).to_str().unwrap().to_string();
+        create_synthetic_mkv(&path("input.mkv"), &path("input.srt"));
+
+        let track = SubtitleTrack::new(vec![Subtitle::new(0.0, 1.0, "Hello".to_string())])
+            .with_language("en")
+            .with_disposition(Disposition::DEFAULT);
+        export_subtitle_to_video(
+            &path("input.mkv"),
+            &path("output.mkv"),
+            &[track],
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+
+        let ictx = format::input(&path("output.mkv")).unwrap();
+        assert_eq!(ictx.streams().count(), 3);
+        assert_eq!(ictx.metadata().get("title"), Some("Synthetic"));
+        let language = |language: &str| {
+            ictx.streams()
+                .find(|stream| stream.metadata().get("language") == Some(language))
+                .unwrap()
+        };
+
+        // The original track keeps its metadata, but the added track is the default one
+        let original = language("jpn");
+        assert_eq!(original.metadata().get("title"), Some("Signs"));
+        assert!(original.disposition().contains(Disposition::FORCED));
+        assert!(!original.disposition().contains(Disposition::DEFAULT));
+        assert!(language("eng").disposition().contains(Disposition::DEFAULT));
+
+        let attachment = ictx
+            .streams()
+            .find(|stream| stream.parameters().medium() == media::Type::Attachment)
+            .unwrap();
+        assert_eq!(attachment.metadata().get("filename"), Some("font.ttf"));
+        let extradata = unsafe {
+            let parameters = attachment.parameters();
+            std::slice::from_raw_parts(
+                (*parameters.as_ptr()).extradata,
+                (*parameters.as_ptr()).extradata_size as usize,
+            )
+            .to_vec()
+        };
+        assert_eq!(extradata, FONT);
+
+        let chapters: Vec<(String, i64, i64)> = ictx
+            .chapters()
+            .map(|chapter| {
+                let millis = |time: i64| {
+                    (time as f64 * f64::from(chapter.time_base()) * 1000.0).round() as i64
+                };
+                (
+                    chapter.metadata().get("title").unwrap_or("").to_string(),
+                    millis(chapter.start()),
+                    millis(chapter.end()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            chapters,
+            vec![
+                ("Opening".to_string(), 0, 2000),
+                ("Ending".to_string(), 2000, 3000)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export_bilingual_without_original_text() {
+        ffmpeg::init().unwrap();
+
+        let tmp_dir = TempDir::new().unwrap();
+        let path = |name: &str| tmp_dir.path().join(name).to_str().unwrap().to_string();
+        create_synthetic_mkv(&path("input.mkv"), &path("input.srt"));
+
+        // Translated by whisper, the subtitles have no original text
+        let subtitles = vec![Subtitle::new(0.0, 1.0, "Hello".to_string())];
+        let tracks = [
+            SubtitleTrack::new(subtitles.clone()).with_language("en"),
+            SubtitleTrack::new(crate::output::original_subtitles(&subtitles)).with_language("ja"),
+        ];
+        export_subtitle_to_video(
+            &path("input.mkv"),
+            &path("output.mkv"),
+            &tracks,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+
+        // The translated track is still muxed after the two streams of the input
+        let ictx = format::input(&path("output.mkv")).unwrap();
+        assert_eq!(ictx.streams().count(), 3);
+        assert!(ictx
+            .streams()
+            .any(|stream| stream.metadata().get("language") == Some("eng")));
+
+        let empty = [SubtitleTrack::new(Vec::new())];
+        assert!(export_subtitle_to_video(
+            &path("input.mkv"),
+            &path("empty.mkv"),
+            &empty,
+            None,
+            None,
+            None
+        )
+        .is_err());
+        assert!(!Path::new(&path("empty.mkv")).exists());
+    }
+
+    #[test]
+    fn test_export_trimmed() {
+        ffmpeg::init().unwrap();
+
+        let tmp_dir = TempDir::new().unwrap();
+        let path = |name: &str| tmp_dir.path().join(name).to_str().unwrap().to_string();
+        create_synthetic_mkv(&path("input.mkv"), &path("input.srt"));
+
+        let track = SubtitleTrack::new(vec![Subtitle::new(2.0, 3.0, "Hello".to_string())]);
+        export_subtitle_to_video(
+            &path("input.mkv"),
+            &path("output.mkv"),
+            &[track],
+            Some(&TimeRange::new(1.5, None)),
+            None,
+            None,
+        )
+        .unwrap();
+
+        // The cue ending before the start is dropped, no stream starts before 0 and the copied
+        // cue is still in sync with the added one
+        let mut ictx = format::input(&path("output.mkv")).unwrap();
+        let mut starts: Vec<(usize, i64)> = ictx
+            .packets()
+            .map(|(stream, packet)| {
+                let pts = packet.pts().unwrap() as f64 * f64::from(stream.time_base());
+                (stream.index(), (pts * 1000.0).round() as i64)
+            })
+            .collect();
+        starts.sort();
+        assert_eq!(starts, vec![(0, 500), (2, 500)]);
+    }
+
+    #[test]
+    fn test_export_in_place() {
+        ffmpeg::init().unwrap();
+
+        let tmp_dir = TempDir::new().unwrap();
+        let path = |name: &str| tmp_dir.path().join(name).to_str().unwrap().to_string();
+        create_synthetic_mkv(&path("video.mkv"), &path("video.srt"));
+        let original = std::fs::read(path("video.mkv")).unwrap();
+
+        let export = || {
+            let track = SubtitleTrack::new(vec![Subtitle::new(0.0, 1.0, "Hello".to_string())]);
+            export_subtitle_to_video(
+                &path("video.mkv"),
+                &path("./video.mkv"),
+                &[track],
+                None,
+                None,
+                Some(&path("video.mkv.bak")),
+            )
+        };
+        export().unwrap();
+        assert_eq!(std::fs::read(path("video.mkv.bak")).unwrap(), original);
+        assert_eq!(format::input(&path("video.mkv")).unwrap().nb_streams(), 3);
+
+        // The backup is never overwritten
+        assert!(export().is_err());
+        assert_eq!(std::fs::read(path("video.mkv.bak")).unwrap(), original);
+        // No temp file is left, only the video, its backup and the synthetic SRT
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_verify_output() {
+        ffmpeg::init().unwrap();
+
+        let tmp_dir = TempDir::new().unwrap();
+        let path = |name: &str| tmp_dir.path().join(name).to_str().unwrap().to_string();
+        create_synthetic_mkv(&path("video.mkv"), &path("video.srt"));
+
+        // A subtitle track with two cues and a font attachment
+        let expected = MuxStats {
+            streams: 2,
+            packets: 0,
+            subtitle_packets: 2,
+            duration: None,
+        };
+        assert!(verify_output(&path("video.mkv"), &expected).is_ok());
+        let missing_stream = MuxStats {
+            streams: 3,
+            ..expected
+        };
+        assert!(verify_output(&path("video.mkv"), &missing_stream).is_err());
+        let missing_packet = MuxStats {
+            subtitle_packets: 3,
+            ..expected
+        };
+        assert!(verify_output(&path("video.mkv"), &missing_packet).is_err());
+        let truncated = MuxStats {
+            duration: Some(60 * ffmpeg::ffi::AV_TIME_BASE as i64),
+            ..expected
+        };
+        assert!(verify_output(&path("video.mkv"), &truncated).is_err());
+        assert!(verify_output(&path("missing.mkv"), &expected).is_err());
+    }
+
+    #[test]
+    fn test_mov_text_packet() {
+        let mut packet = Packet::copy("Hello".as_bytes());
+        packet.set_pts(Some(1000));
+        packet.set_duration(500);
+        let mov_text = mov_text_packet(&packet);
+        assert_eq!(mov_text.data(), Some(&b"\x00\x05Hello"[..]));
+        assert_eq!(mov_text.pts(), Some(1000));
+        assert_eq!(mov_text.duration(), 500);
+    }
 }
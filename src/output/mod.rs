@@ -1,8 +1,36 @@
 use crate::utils::Subtitle;
 
+pub mod ass;
+pub mod ffmpeg_burn_in;
 pub mod ffmpeg_subtitle;
 pub mod srt;
+pub mod vtt;
 
 pub trait OutputSubtitles {
     fn output_subtitles(&mut self, subtitles: &[Subtitle]);
 }
+
+/// The original text stacked above the translation, or the text alone if not translated
+pub(crate) fn bilingual_text(subtitle: &Subtitle) -> String {
+    match &subtitle.original_text {
+        Some(original_text) => format!("{}\n{}", original_text.trim(), subtitle.text.trim()),
+        None => subtitle.text.trim().to_string(),
+    }
+}
+
+/// The subtitles with their original text, dropping the ones not translated
+pub(crate) fn original_subtitles(subtitles: &[Subtitle]) -> Vec<Subtitle> {
+    subtitles
+        .iter()
+        .filter_map(|subtitle| {
+            subtitle
+                .original_text
+                .as_ref()
+                .map(|original_text| Subtitle {
+                    text: original_text.clone(),
+                    original_text: None,
+                    ..subtitle.clone()
+                })
+        })
+        .collect()
+}
@@ -1,15 +1,20 @@
-use crate::output::OutputSubtitles;
 use crate::output::Subtitle;
+use crate::output::{bilingual_text, OutputSubtitles};
 use std::fs::File;
 use std::io::Write;
 
 pub(crate) struct SrtSubtitleExporter {
     pub file: File,
+    /// Stack the original text above the translation
+    pub bilingual: bool,
 }
 
 impl SrtSubtitleExporter {
     pub fn new(file: File) -> SrtSubtitleExporter {
-        SrtSubtitleExporter { file }
+        SrtSubtitleExporter {
+            file,
+            bilingual: false,
+        }
     }
 }
 
@@ -35,7 +40,11 @@ impl OutputSubtitles for SrtSubtitleExporter {
                 format_time(subtitle.end)
             ));
             srt.push('\n');
-            srt.push_str(subtitle.text.trim());
+            if self.bilingual {
+                srt.push_str(&bilingual_text(subtitle));
+            } else {
+                srt.push_str(subtitle.text.trim());
+            }
             srt.push_str("\n\n");
         }
         self.file.write_all(srt.as_bytes()).unwrap();
@@ -60,11 +69,13 @@ mod tests {
                 start: 0.0,
                 end: 1.0,
                 text: "Hello, world!".to_string(),
+                ..Default::default()
             },
             Subtitle {
                 start: 1.0,
                 end: 2.0,
                 text: "Goodbye, world!".to_string(),
+                ..Default::default()
             },
         ];
         exporter.output_subtitles(&subtitles);
@@ -84,4 +95,49 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_output_bilingual_subtitles() {
+        use std::io::Read;
+        use tempfile::TempDir;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let tmp_path = tmp_dir.path().join("test.srt");
+        let file = File::create(&tmp_path).unwrap();
+        let mut exporter = SrtSubtitleExporter::new(file);
+        exporter.bilingual = true;
+        let subtitles = vec![
+            Subtitle {
+                start: 0.0,
+                end: 1.0,
+                text: "Hello, world!".to_string(),
+                original_text: Some("こんにちは、世界！".to_string()),
+                ..Default::default()
+            },
+            // Not translated, e.g. already in the target language
+            Subtitle {
+                start: 1.0,
+                end: 2.0,
+                text: "OK".to_string(),
+                ..Default::default()
+            },
+        ];
+        exporter.output_subtitles(&subtitles);
+
+        let mut contents = String::new();
+        File::open(tmp_path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(
+            contents,
+            format!(
+                "1\n{} --> {}\nこんにちは、世界！\nHello, world!\n\n2\n{} --> {}\nOK\n\n",
+                format_time(0.0),
+                format_time(1.0),
+                format_time(1.0),
+                format_time(2.0)
+            )
+        );
+    }
 }
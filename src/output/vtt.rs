@@ -0,0 +1,224 @@
+use crate::output::Subtitle;
+use crate::output::{bilingual_text, OutputSubtitles};
+use std::fs::File;
+use std::io::Write;
+
+/// WebVTT cue settings applied to every cue, e.g. "position:50% line:85% align:center"
+#[derive(Default, Debug, PartialEq)]
+pub struct VttCueSettings {
+    pub position: Option<String>,
+    pub line: Option<String>,
+    pub align: Option<String>,
+    pub size: Option<String>,
+}
+
+impl VttCueSettings {
+    pub fn parse(settings: &str) -> Result<VttCueSettings, String> {
+        let mut cue_settings = VttCueSettings::default();
+        for setting in settings.split_whitespace() {
+            let (name, value) = setting
+                .split_once(':')
+                .ok_or(format!("Invalid cue setting {}", setting))?;
+            let value = Some(value.to_string());
+            match name {
+                "position" => cue_settings.position = value,
+                "line" => cue_settings.line = value,
+                "align" => cue_settings.align = value,
+                "size" => cue_settings.size = value,
+                _ => return Err(format!("Unsupported cue setting {}", name)),
+            }
+        }
+        Ok(cue_settings)
+    }
+
+    fn format(&self) -> String {
+        [
+            ("position", &self.position),
+            ("line", &self.line),
+            ("align", &self.align),
+            ("size", &self.size),
+        ]
+        .iter()
+        .filter_map(|(name, value)| value.as_ref().map(|v| format!("{}:{}", name, v)))
+        .collect::<Vec<String>>()
+        .join(" ")
+    }
+}
+
+pub(crate) struct VttSubtitleExporter {
+    pub file: File,
+    pub cue_settings: VttCueSettings,
+    pub notes: Vec<String>,
+    pub style: Option<String>,
+    /// Stack the original text above the translation
+    pub bilingual: bool,
+}
+
+impl VttSubtitleExporter {
+    pub fn new(file: File) -> VttSubtitleExporter {
+        VttSubtitleExporter {
+            file,
+            cue_settings: VttCueSettings::default(),
+            notes: Vec::new(),
+            style: None,
+            bilingual: false,
+        }
+    }
+}
+
+fn format_time(time: f32) -> String {
+    let millis = (time.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis % 3_600_000 / 60_000,
+        millis % 60_000 / 1000,
+        millis % 1000
+    )
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Blocks end at the first blank line, so blank lines are dropped from the content
+fn remove_blank_lines(text: &str) -> String {
+    text.lines()
+        .map(|line| line.trim_end())
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+impl OutputSubtitles for VttSubtitleExporter {
+    fn output_subtitles(&mut self, subtitles: &[Subtitle]) {
+        let mut vtt = String::from("WEBVTT\n\n");
+        if let Some(style) = &self.style {
+            vtt.push_str("STYLE\n");
+            vtt.push_str(&remove_blank_lines(style));
+            vtt.push_str("\n\n");
+        }
+        for note in self.notes.iter() {
+            vtt.push_str("NOTE\n");
+            vtt.push_str(&remove_blank_lines(&note.replace("-->", "->")));
+            vtt.push_str("\n\n");
+        }
+
+        let cue_settings = self.cue_settings.format();
+        for (i, subtitle) in subtitles.iter().enumerate() {
+            vtt.push_str(&(i + 1).to_string());
+            vtt.push('\n');
+            vtt.push_str(&format!(
+                "{} --> {}",
+                format_time(subtitle.start),
+                format_time(subtitle.end)
+            ));
+            if !cue_settings.is_empty() {
+                vtt.push(' ');
+                vtt.push_str(&cue_settings);
+            }
+            vtt.push('\n');
+            let text = if self.bilingual {
+                bilingual_text(subtitle)
+            } else {
+                subtitle.text.trim().to_string()
+            };
+            vtt.push_str(&remove_blank_lines(&escape_text(&text)));
+            vtt.push_str("\n\n");
+        }
+        self.file.write_all(vtt.as_bytes()).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_subtitles() {
+        use std::io::Read;
+        use tempfile::TempDir;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let tmp_path = tmp_dir.path().join("test.vtt");
+        let file = File::create(tmp_path).unwrap();
+        let mut exporter = VttSubtitleExporter::new(file);
+        let subtitles = vec![
+            Subtitle {
+                start: 0.0,
+                end: 1.0,
+                text: "Hello, world!".to_string(),
+                ..Default::default()
+            },
+            Subtitle {
+                start: 1.0,
+                end: 3723.5,
+                text: "Goodbye, <world> & co!".to_string(),
+                ..Default::default()
+            },
+        ];
+        exporter.output_subtitles(&subtitles);
+
+        let tmp_path = tmp_dir.path().join("test.vtt");
+        let mut file = File::open(tmp_path).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(
+            contents,
+            "WEBVTT\n\n\
+            1\n00:00:00.000 --> 00:00:01.000\nHello, world!\n\n\
+            2\n00:00:01.000 --> 01:02:03.500\nGoodbye, &lt;world&gt; &amp; co!\n\n"
+        );
+    }
+
+    #[test]
+    fn test_output_subtitles_with_settings() {
+        use std::io::Read;
+        use tempfile::TempDir;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let tmp_path = tmp_dir.path().join("test.vtt");
+        let file = File::create(tmp_path).unwrap();
+        let mut exporter = VttSubtitleExporter::new(file);
+        exporter.cue_settings = VttCueSettings::parse("line:85% align:center").unwrap();
+        exporter
+            .notes
+            .push("Generated by AI no jimaku gumi".to_string());
+        exporter.style = Some("::cue {\n  color: yellow;\n}".to_string());
+        let subtitles = vec![Subtitle {
+            start: 0.0,
+            end: 1.0,
+            text: "Hello, world!".to_string(),
+            ..Default::default()
+        }];
+        exporter.output_subtitles(&subtitles);
+
+        let tmp_path = tmp_dir.path().join("test.vtt");
+        let mut file = File::open(tmp_path).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(
+            contents,
+            "WEBVTT\n\n\
+            STYLE\n::cue {\n  color: yellow;\n}\n\n\
+            NOTE\nGenerated by AI no jimaku gumi\n\n\
+            1\n00:00:00.000 --> 00:00:01.000 line:85% align:center\nHello, world!\n\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_cue_settings() {
+        assert_eq!(
+            VttCueSettings::parse("position:10% align:start").unwrap(),
+            VttCueSettings {
+                position: Some("10%".to_string()),
+                align: Some("start".to_string()),
+                ..Default::default()
+            }
+        );
+        assert!(VttCueSettings::parse("vertical").is_err());
+        assert!(VttCueSettings::parse("color:red").is_err());
+    }
+}
@@ -1,7 +1,28 @@
+use super::{Translator, TranslatorCapabilities};
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt, TryStreamExt};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 
+// Check https://developers.deepl.com/docs/resources/usage-limits
+const MAX_TEXTS_PER_REQUEST: usize = 50;
+// The request size limit is 128 KiB, keep some room for the JSON overhead
+const MAX_BYTES_PER_REQUEST: usize = 100 * 1024;
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 4;
+
+// Check https://developers.deepl.com/docs/resources/supported-languages
+const SOURCE_LANGUAGES: &[&str] = &[
+    "AR", "BG", "CS", "DA", "DE", "EL", "EN", "ES", "ET", "FI", "FR", "HE", "HU", "ID", "IT", "JA",
+    "KO", "LT", "LV", "NB", "NL", "PL", "PT", "RO", "RU", "SK", "SL", "SV", "TH", "TR", "UK", "VI",
+    "ZH",
+];
+const TARGET_LANGUAGES: &[&str] = &[
+    "AR", "BG", "CS", "DA", "DE", "EL", "EN", "EN-GB", "EN-US", "ES", "ET", "FI", "FR", "HE", "HU",
+    "ID", "IT", "JA", "KO", "LT", "LV", "NB", "NL", "PL", "PT", "PT-BR", "PT-PT", "RO", "RU", "SK",
+    "SL", "SV", "TH", "TR", "UK", "VI", "ZH-HANS", "ZH-HANT",
+];
+
 #[derive(Serialize)]
 struct TranslateRequest<'a> {
     text: Vec<&'a str>,
@@ -20,14 +41,15 @@ struct Translation {
 }
 
 async fn _translate_text(
+    client: &Client,
     base_url: &str,
     path: &str,
     api_key: &str,
     texts: Vec<&str>,
     target_lang: &str,
     source_lang: Option<&str>,
-) -> Result<String, Box<dyn Error>> {
-    let client = Client::new();
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let text_count = texts.len();
     let request_body = TranslateRequest {
         text: texts,
         source_lang,
@@ -43,38 +65,157 @@ async fn _translate_text(
 
     if response.status().is_success() {
         let translate_response: TranslateResponse = response.json().await?;
-        if let Some(translation) = translate_response.translations.first() {
-            Ok(translation.text.clone())
-        } else {
-            Err("No translation found".into())
+        if translate_response.translations.len() != text_count {
+            return Err(format!(
+                "Expected {} translations, got {}",
+                text_count,
+                translate_response.translations.len()
+            )
+            .into());
         }
+        Ok(translate_response
+            .translations
+            .into_iter()
+            .map(|translation| translation.text)
+            .collect())
     } else {
         Err(format!("Failed to translate text: {:?}", response.text().await?).into())
     }
 }
 
+/// Split the texts into batches respecting the number of texts and size limits of a request
+fn split_into_batches<'a>(
+    texts: &[&'a str],
+    max_texts: usize,
+    max_bytes: usize,
+) -> Vec<Vec<&'a str>> {
+    let mut batches: Vec<Vec<&str>> = Vec::new();
+    let mut batch_bytes = 0;
+    for &text in texts {
+        match batches.last_mut() {
+            Some(batch) if batch.len() < max_texts && batch_bytes + text.len() <= max_bytes => {
+                batch.push(text);
+                batch_bytes += text.len();
+            }
+            // A text larger than the limit still gets its own batch
+            _ => {
+                batches.push(vec![text]);
+                batch_bytes = text.len();
+            }
+        }
+    }
+    batches
+}
+
+async fn _translate_batches(
+    base_url: &str,
+    path: &str,
+    api_key: &str,
+    texts: Vec<&str>,
+    target_lang: &str,
+    source_lang: Option<&str>,
+    max_concurrent_requests: usize,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let client = Client::new();
+    let batches = split_into_batches(&texts, MAX_TEXTS_PER_REQUEST, MAX_BYTES_PER_REQUEST);
+    // `buffered` keeps the order of the batches while running them concurrently
+    let translations: Vec<Vec<String>> = stream::iter(batches)
+        .map(|batch| {
+            _translate_text(
+                &client,
+                base_url,
+                path,
+                api_key,
+                batch,
+                target_lang,
+                source_lang,
+            )
+        })
+        .buffered(max_concurrent_requests.max(1))
+        .try_collect()
+        .await?;
+    Ok(translations.into_iter().flatten().collect())
+}
+
+/// Translate the texts, the translations are returned in the same order
 pub async fn translate_text(
     api_key: &str,
     texts: Vec<&str>,
     target_lang: &str,
     source_lang: Option<&str>,
-) -> Result<String, Box<dyn Error>> {
+) -> Result<Vec<String>, Box<dyn Error>> {
     let base_url =
         std::env::var("DEEPL_API_URL").unwrap_or("https://api-free.deepl.com".to_string());
     let path_url = std::env::var("DEEPL_API_URL_PATH").unwrap_or("/v2/translate".to_string());
-    let deepl_source_lang = get_deepl_source_language(source_lang);
+    let max_concurrent_requests = std::env::var("DEEPL_MAX_CONCURRENT_REQUESTS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(DEFAULT_MAX_CONCURRENT_REQUESTS);
+    // DeepL detects the source language if it is not given
+    let deepl_source_lang = match source_lang {
+        Some("auto") => None,
+        lang => Some(get_deepl_source_language(lang)),
+    };
     let deepl_target_lang = get_deepl_target_language(target_lang);
-    _translate_text(
+    _translate_batches(
         &base_url,
         &path_url,
         api_key,
         texts,
         deepl_target_lang.as_str(),
-        Some(deepl_source_lang.as_str()),
+        deepl_source_lang.as_deref(),
+        max_concurrent_requests,
     )
     .await
 }
 
+pub struct DeepLTranslator {
+    api_key: String,
+}
+
+impl DeepLTranslator {
+    pub fn new(api_key: String) -> DeepLTranslator {
+        DeepLTranslator { api_key }
+    }
+
+    /// Use the API key from the `DEEPL_API_KEY` env
+    pub fn from_env() -> DeepLTranslator {
+        DeepLTranslator::new(std::env::var("DEEPL_API_KEY").unwrap_or_default())
+    }
+}
+
+#[async_trait(?Send)]
+impl Translator for DeepLTranslator {
+    fn name(&self) -> &str {
+        "deepl"
+    }
+
+    fn capabilities(&self) -> TranslatorCapabilities {
+        TranslatorCapabilities {
+            batch: true,
+            ..Default::default()
+        }
+    }
+
+    fn supports_language(&self, source_lang: &str, target_lang: &str) -> bool {
+        (source_lang == "auto"
+            || SOURCE_LANGUAGES.contains(&get_deepl_source_language(Some(source_lang)).as_str()))
+            && TARGET_LANGUAGES.contains(&get_deepl_target_language(target_lang).as_str())
+    }
+
+    async fn translate(
+        &self,
+        texts: Vec<&str>,
+        source_lang: &str,
+        target_lang: &str,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        if self.api_key.is_empty() {
+            return Err("DEEPL_API_KEY is not set".into());
+        }
+        translate_text(&self.api_key, texts, target_lang, Some(source_lang)).await
+    }
+}
+
 fn get_deepl_source_language(source_lang: Option<&str>) -> String {
     if let Some(lang) = source_lang {
         lang.to_uppercase()
@@ -127,6 +268,7 @@ mod tests {
         let rt = tokio::runtime::Runtime::new().unwrap();
         let result = rt.block_on(async {
             _translate_text(
+                &Client::new(),
                 server.url().as_str(),
                 "/v2/translate",
                 api_key,
@@ -139,6 +281,108 @@ mod tests {
 
         // Assert the result
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "Hallo Welt");
+        assert_eq!(result.unwrap(), vec!["Hallo Welt"]);
+    }
+
+    #[test]
+    fn test_supports_language() {
+        let translator = DeepLTranslator::new("test_api_key".to_string());
+        assert!(translator.supports_language("ja", "en"));
+        assert!(translator.supports_language("en", "zh"));
+        assert!(translator.supports_language("en", "tw"));
+        assert!(!translator.supports_language("ja", "tlh"));
+        assert!(!translator.supports_language("tlh", "en"));
+        assert!(translator.supports_language("auto", "en"));
+    }
+
+    #[test]
+    fn test_split_into_batches() {
+        let texts = vec!["a"; 120];
+        let batches = split_into_batches(&texts, MAX_TEXTS_PER_REQUEST, MAX_BYTES_PER_REQUEST);
+        assert_eq!(
+            batches.iter().map(|b| b.len()).collect::<Vec<_>>(),
+            vec![50, 50, 20]
+        );
+
+        let texts = vec!["aaaa", "bbbb", "cccccccccc", "d"];
+        let batches = split_into_batches(&texts, 50, 8);
+        assert_eq!(
+            batches,
+            vec![vec!["aaaa", "bbbb"], vec!["cccccccccc"], vec!["d"]]
+        );
+    }
+
+    #[test]
+    fn test_translate_batches_sync() {
+        let mut server = Server::new();
+
+        // Echo the texts in upper case, in the same order
+        let mock = server
+            .mock("POST", "/v2/translate")
+            .match_header("Authorization", "DeepL-Auth-Key test_api_key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body_from_request(|request| {
+                let body: serde_json::Value =
+                    serde_json::from_slice(request.body().unwrap()).unwrap();
+                let translations: Vec<serde_json::Value> = body["text"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|t| serde_json::json!({"text": t.as_str().unwrap().to_uppercase()}))
+                    .collect();
+                serde_json::json!({ "translations": translations })
+                    .to_string()
+                    .into_bytes()
+            })
+            .expect(3)
+            .create();
+
+        let texts: Vec<String> = (0..120).map(|i| format!("line {}", i)).collect();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let result = rt.block_on(async {
+            _translate_batches(
+                server.url().as_str(),
+                "/v2/translate",
+                "test_api_key",
+                texts.iter().map(|t| t.as_str()).collect(),
+                "DE",
+                Some("EN"),
+                2,
+            )
+            .await
+        });
+
+        mock.assert();
+        let translations = result.unwrap();
+        assert_eq!(translations.len(), 120);
+        assert_eq!(translations[0], "LINE 0");
+        assert_eq!(translations[119], "LINE 119");
+    }
+
+    #[test]
+    fn test_translate_text_count_mismatch() {
+        let mut server = Server::new();
+        let _m = server
+            .mock("POST", "/v2/translate")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"translations":[{"text":"Hallo"}]}"#)
+            .create();
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let result = rt.block_on(async {
+            _translate_text(
+                &Client::new(),
+                server.url().as_str(),
+                "/v2/translate",
+                "test_api_key",
+                vec!["Hello", "World"],
+                "DE",
+                Some("EN"),
+            )
+            .await
+        });
+        assert!(result.is_err());
     }
 }
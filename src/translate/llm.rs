@@ -1,7 +1,40 @@
+use super::{Translator, TranslatorCapabilities, TranslatorConfig};
+use async_trait::async_trait;
+use genai::adapter::AdapterKind;
 use genai::chat::{ChatMessage, ChatRequest};
-use genai::Client;
+use genai::resolver::{AuthData, Endpoint, ServiceTargetResolver};
+use genai::{Client, ModelIden, ServiceTarget};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::error::Error;
 
+/// Build a `genai` client, using an OpenAI compatible API at `api_base` if not empty
+pub fn build_client(api_base: &str, model_name: &str) -> Client {
+    if api_base.is_empty() {
+        return Client::default();
+    }
+
+    let api_base = api_base.to_string();
+    let model_name = model_name.to_string();
+    // -- Build an auth_resolver and the AdapterConfig
+    // link https://github.com/jeremychone/rust-genai/blob/main/examples/c06-target-resolver.rs
+    let target_resolver = ServiceTargetResolver::from_resolver_fn(
+        move |_service_target: ServiceTarget| -> Result<ServiceTarget, genai::resolver::Error> {
+            let endpoint = Endpoint::from_owned(api_base.clone());
+            let auth = AuthData::from_env("CUSTOM_API_KEY");
+            let model = ModelIden::new(AdapterKind::OpenAI, model_name.clone());
+            Ok(ServiceTarget {
+                endpoint,
+                auth,
+                model,
+            })
+        },
+    );
+    Client::builder()
+        .with_service_target_resolver(target_resolver)
+        .build()
+}
+
 pub async fn translate_text(
     client: &Client,
     model: &str,
@@ -16,3 +49,410 @@ pub async fn translate_text(
     let response = client.exec_chat(model, chat_req, None).await?;
     Ok(response.content_text_as_str().unwrap_or("").to_string())
 }
+
+pub struct WindowOptions {
+    /// Number of lines translated in one request
+    pub window_size: usize,
+    /// Number of preceding lines sent (with their translation) as context
+    pub context_size: usize,
+    /// Number of retries of a window before falling back to line by line translation
+    pub max_retries: usize,
+}
+
+impl Default for WindowOptions {
+    fn default() -> Self {
+        WindowOptions {
+            window_size: 20,
+            context_size: 5,
+            max_retries: 2,
+        }
+    }
+}
+
+const WINDOW_INSTRUCTIONS: &str = "You translate subtitles of a video. \
+The user sends a JSON object with `context`, the preceding lines and their translation \
+(for reference only, do not translate them again), and `lines`, the lines to translate. \
+A sentence might be split across several lines, keep names and pronouns consistent. \
+Reply only with a JSON array containing, for every line, an object \
+{\"id\": <the line id>, \"text\": <the translation>}, each id exactly once.";
+
+#[derive(Serialize)]
+struct ContextLine<'a> {
+    text: &'a str,
+    translation: &'a str,
+}
+
+#[derive(Serialize)]
+struct WindowLine<'a> {
+    id: usize,
+    text: &'a str,
+}
+
+#[derive(Serialize)]
+struct WindowRequest<'a> {
+    context: Vec<ContextLine<'a>>,
+    lines: Vec<WindowLine<'a>>,
+}
+
+#[derive(Deserialize)]
+struct TranslatedLine {
+    id: usize,
+    text: String,
+}
+
+/// Parse the model reply and check that every id of the window comes back exactly once
+fn parse_window_response(
+    response: &str,
+    ids: &[usize],
+) -> Result<HashMap<usize, String>, Box<dyn Error>> {
+    // Models tend to wrap the JSON in a markdown code block
+    let start = response.find('[').ok_or("No JSON array in the response")?;
+    let end = response.rfind(']').ok_or("No JSON array in the response")?;
+    if end < start {
+        return Err("No JSON array in the response".into());
+    }
+    let lines: Vec<TranslatedLine> = serde_json::from_str(&response[start..=end])?;
+
+    let mut translations = HashMap::new();
+    for line in lines {
+        if !ids.contains(&line.id) {
+            return Err(format!("Unexpected line id {}", line.id).into());
+        }
+        if translations.insert(line.id, line.text).is_some() {
+            return Err(format!("Duplicated line id {}", line.id).into());
+        }
+    }
+    if let Some(missing) = ids.iter().find(|id| !translations.contains_key(id)) {
+        return Err(format!("Missing line id {}", missing).into());
+    }
+    Ok(translations)
+}
+
+async fn translate_window(
+    client: &Client,
+    model: &str,
+    sys_prompt: &str,
+    request: &WindowRequest<'_>,
+    ids: &[usize],
+) -> Result<HashMap<usize, String>, Box<dyn Error>> {
+    let chat_req = ChatRequest::new(vec![
+        ChatMessage::system(format!("{}\n{}", sys_prompt, WINDOW_INSTRUCTIONS)),
+        ChatMessage::user(serde_json::to_string(request)?),
+    ]);
+
+    let response = client.exec_chat(model, chat_req, None).await?;
+    parse_window_response(response.content_text_as_str().unwrap_or(""), ids)
+}
+
+/// Translate the texts by windows of lines with the preceding lines as context
+///
+/// Windows are retried if the reply does not match the lines, then translated line by line.
+pub async fn translate_text_with_context(
+    client: &Client,
+    model: &str,
+    sys_prompt: &str,
+    texts: Vec<&str>,
+    options: &WindowOptions,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut translations: Vec<String> = Vec::with_capacity(texts.len());
+    let window_size = options.window_size.max(1);
+    for window_start in (0..texts.len()).step_by(window_size) {
+        let window_end = (window_start + window_size).min(texts.len());
+        let context_start = window_start.saturating_sub(options.context_size);
+        let request = WindowRequest {
+            context: (context_start..window_start)
+                .map(|i| ContextLine {
+                    text: texts[i],
+                    translation: translations[i].as_str(),
+                })
+                .collect(),
+            lines: (window_start..window_end)
+                .map(|i| WindowLine {
+                    id: i + 1,
+                    text: texts[i],
+                })
+                .collect(),
+        };
+        let ids: Vec<usize> = request.lines.iter().map(|line| line.id).collect();
+
+        let mut window_translations = None;
+        for attempt in 0..=options.max_retries {
+            match translate_window(client, model, sys_prompt, &request, &ids).await {
+                Ok(result) => {
+                    window_translations = Some(result);
+                    break;
+                }
+                Err(e) => println!(
+                    "Failed to translate lines {}-{} (attempt {}): {}",
+                    window_start + 1,
+                    window_end,
+                    attempt + 1,
+                    e
+                ),
+            }
+        }
+
+        match window_translations {
+            Some(mut result) => {
+                for id in ids {
+                    translations.push(result.remove(&id).unwrap());
+                }
+            }
+            None => {
+                println!(
+                    "Falling back to line by line translation for lines {}-{}",
+                    window_start + 1,
+                    window_end
+                );
+                for &text in &texts[window_start..window_end] {
+                    translations.push(translate_text(client, model, sys_prompt, vec![text]).await?);
+                }
+            }
+        }
+    }
+    Ok(translations)
+}
+
+/// The source language is left to the model if unknown ("auto")
+fn default_prompt(source_lang: &str, target_lang: &str) -> String {
+    if source_lang == "auto" {
+        format!("Translate the following text. to language {}", target_lang)
+    } else {
+        format!(
+            "Translate the following text from language {} to language {}",
+            source_lang, target_lang
+        )
+    }
+}
+
+pub struct LlmTranslator {
+    client: Client,
+    model: String,
+    prompt: String,
+    /// Translate by windows of lines, or line by line if `None`
+    window_options: Option<WindowOptions>,
+}
+
+impl LlmTranslator {
+    pub fn new(config: &TranslatorConfig) -> LlmTranslator {
+        let window_options = if config.llm_window_size > 0 {
+            Some(WindowOptions {
+                window_size: config.llm_window_size,
+                context_size: config.llm_context_size,
+                ..Default::default()
+            })
+        } else {
+            None
+        };
+        LlmTranslator {
+            client: build_client(&config.llm_api_base, &config.llm_model_name),
+            model: config.llm_model_name.clone(),
+            prompt: config.llm_prompt.clone(),
+            window_options,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Translator for LlmTranslator {
+    fn name(&self) -> &str {
+        "llm"
+    }
+
+    fn capabilities(&self) -> TranslatorCapabilities {
+        TranslatorCapabilities {
+            batch: self.window_options.is_some(),
+            context: self.window_options.is_some(),
+            ..Default::default()
+        }
+    }
+
+    fn supports_language(&self, _source_lang: &str, _target_lang: &str) -> bool {
+        // Up to the model and the prompt
+        true
+    }
+
+    async fn translate(
+        &self,
+        texts: Vec<&str>,
+        source_lang: &str,
+        target_lang: &str,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let system_prompt = if !self.prompt.is_empty() {
+            self.prompt.clone()
+        } else {
+            default_prompt(source_lang, target_lang)
+        };
+        match &self.window_options {
+            Some(options) => {
+                translate_text_with_context(
+                    &self.client,
+                    &self.model,
+                    &system_prompt,
+                    texts,
+                    options,
+                )
+                .await
+            }
+            None => {
+                let mut translations = Vec::with_capacity(texts.len());
+                for text in texts {
+                    translations.push(
+                        translate_text(&self.client, &self.model, &system_prompt, vec![text])
+                            .await?,
+                    );
+                }
+                Ok(translations)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Server;
+
+    fn chat_completion(content: &str) -> Vec<u8> {
+        serde_json::json!({
+            "id": "chatcmpl-test",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "test-model",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": content},
+                "finish_reason": "stop"
+            }],
+            "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2}
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    /// Content of the last message of a chat completion request
+    fn last_message(request: &mockito::Request) -> String {
+        let body: serde_json::Value = serde_json::from_slice(request.body().unwrap()).unwrap();
+        let messages = body["messages"].as_array().unwrap();
+        messages.last().unwrap()["content"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_default_prompt() {
+        assert_eq!(
+            default_prompt("ja", "en"),
+            "Translate the following text from language ja to language en"
+        );
+        assert_eq!(
+            default_prompt("auto", "en"),
+            "Translate the following text. to language en"
+        );
+    }
+
+    #[test]
+    fn test_parse_window_response() {
+        let response = "```json\n[{\"id\": 2, \"text\": \"b\"}, {\"id\": 1, \"text\": \"a\"}]\n```";
+        let translations = parse_window_response(response, &[1, 2]).unwrap();
+        assert_eq!(translations[&1], "a");
+        assert_eq!(translations[&2], "b");
+
+        assert!(parse_window_response("[{\"id\": 1, \"text\": \"a\"}]", &[1, 2]).is_err());
+        assert!(parse_window_response(
+            "[{\"id\": 1, \"text\": \"a\"}, {\"id\": 1, \"text\": \"a\"}]",
+            &[1]
+        )
+        .is_err());
+        assert!(parse_window_response("[{\"id\": 3, \"text\": \"c\"}]", &[3, 4]).is_err());
+        assert!(parse_window_response("Sorry, I can't", &[1]).is_err());
+    }
+
+    #[test]
+    fn test_translate_text_with_context() {
+        let mut server = Server::new();
+
+        // Translate windows by upper-casing the lines, and check the context is sent
+        let mock = server
+            .mock("POST", "/v1/chat/completions")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body_from_request(|request| {
+                let message: serde_json::Value =
+                    serde_json::from_str(&last_message(request)).unwrap();
+                let context = message["context"].as_array().unwrap();
+                let lines = message["lines"].as_array().unwrap();
+                if lines[0]["id"] != 1 {
+                    assert_eq!(context.len(), 1);
+                    assert_eq!(
+                        context[0]["translation"],
+                        context[0]["text"].as_str().unwrap().to_uppercase()
+                    );
+                }
+                let translations: Vec<serde_json::Value> = lines
+                    .iter()
+                    .map(|line| {
+                        serde_json::json!({
+                            "id": line["id"],
+                            "text": line["text"].as_str().unwrap().to_uppercase()
+                        })
+                    })
+                    .collect();
+                chat_completion(&serde_json::to_string(&translations).unwrap())
+            })
+            .expect(3)
+            .create();
+
+        std::env::set_var("CUSTOM_API_KEY", "test_api_key");
+        let client = build_client(&format!("{}/v1/", server.url()), "test-model");
+        let options = WindowOptions {
+            window_size: 2,
+            context_size: 1,
+            max_retries: 0,
+        };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let result = rt.block_on(translate_text_with_context(
+            &client,
+            "test-model",
+            "Translate to English",
+            vec!["a", "b", "c", "d", "e"],
+            &options,
+        ));
+
+        mock.assert();
+        assert_eq!(result.unwrap(), vec!["A", "B", "C", "D", "E"]);
+    }
+
+    #[test]
+    fn test_translate_text_with_context_fallback() {
+        let mut server = Server::new();
+
+        // Windows always miss a line, single lines are answered in plain text
+        let _m = server
+            .mock("POST", "/v1/chat/completions")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body_from_request(|request| {
+                let message = last_message(request);
+                match serde_json::from_str::<serde_json::Value>(&message) {
+                    Ok(_) => chat_completion("[{\"id\": 1, \"text\": \"A\"}]"),
+                    Err(_) => chat_completion(&format!("{}!", message)),
+                }
+            })
+            .create();
+
+        std::env::set_var("CUSTOM_API_KEY", "test_api_key");
+        let client = build_client(&format!("{}/v1/", server.url()), "test-model");
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let result = rt.block_on(translate_text_with_context(
+            &client,
+            "test-model",
+            "Translate to English",
+            vec!["a", "b"],
+            &WindowOptions::default(),
+        ));
+
+        assert_eq!(result.unwrap(), vec!["a!", "b!"]);
+    }
+}
@@ -1,2 +1,171 @@
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::error::Error;
+
 pub mod deepl;
 pub mod llm;
+pub mod whisper;
+
+/// What a translator backend is able to do
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TranslatorCapabilities {
+    /// Several texts are translated in a single request
+    pub batch: bool,
+    /// Translations take the neighbouring lines into account
+    pub context: bool,
+    /// Texts are translated from the audio while transcribing, e.g. whisper
+    pub speech: bool,
+}
+
+#[async_trait(?Send)]
+pub trait Translator {
+    /// Name used to select the backend, e.g. "deepl"
+    fn name(&self) -> &str;
+
+    fn capabilities(&self) -> TranslatorCapabilities;
+
+    /// Whether the backend translates from `source_lang` to `target_lang`
+    fn supports_language(&self, source_lang: &str, target_lang: &str) -> bool;
+
+    /// Translate the texts, the translations are returned in the same order
+    async fn translate(
+        &self,
+        texts: Vec<&str>,
+        source_lang: &str,
+        target_lang: &str,
+    ) -> Result<Vec<String>, Box<dyn Error>>;
+}
+
+/// Settings of the translator backends, each backend picks what it needs
+#[derive(Clone, Debug, Default)]
+pub struct TranslatorConfig {
+    pub llm_model_name: String,
+    pub llm_api_base: String,
+    pub llm_prompt: String,
+    pub llm_window_size: usize,
+    pub llm_context_size: usize,
+}
+
+pub type TranslatorFactory =
+    Box<dyn Fn(&TranslatorConfig) -> Result<Box<dyn Translator>, Box<dyn Error>>>;
+
+/// Translator backends resolved by name
+pub struct TranslatorRegistry {
+    factories: BTreeMap<String, TranslatorFactory>,
+}
+
+impl TranslatorRegistry {
+    /// An empty registry, see `TranslatorRegistry::default` for the built-in backends
+    pub fn new() -> TranslatorRegistry {
+        TranslatorRegistry {
+            factories: BTreeMap::new(),
+        }
+    }
+
+    /// Register a backend, replacing any backend with the same name
+    pub fn register<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn(&TranslatorConfig) -> Result<Box<dyn Translator>, Box<dyn Error>> + 'static,
+    {
+        self.factories.insert(name.to_string(), Box::new(factory));
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.factories.keys().map(|name| name.as_str()).collect()
+    }
+
+    pub fn create(
+        &self,
+        name: &str,
+        config: &TranslatorConfig,
+    ) -> Result<Box<dyn Translator>, Box<dyn Error>> {
+        match self.factories.get(name) {
+            Some(factory) => factory(config),
+            None => Err(format!(
+                "Unsupported translator backend now {} (available: {})",
+                name,
+                self.names().join(", ")
+            )
+            .into()),
+        }
+    }
+}
+
+impl Default for TranslatorRegistry {
+    fn default() -> Self {
+        let mut registry = TranslatorRegistry::new();
+        registry.register("deepl", |_| {
+            Ok(Box::new(deepl::DeepLTranslator::from_env()))
+        });
+        registry.register("llm", |config| {
+            Ok(Box::new(llm::LlmTranslator::new(config)))
+        });
+        registry.register("whisper", |_| Ok(Box::new(whisper::WhisperTranslator)));
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct UppercaseTranslator;
+
+    #[async_trait(?Send)]
+    impl Translator for UppercaseTranslator {
+        fn name(&self) -> &str {
+            "uppercase"
+        }
+
+        fn capabilities(&self) -> TranslatorCapabilities {
+            TranslatorCapabilities {
+                batch: true,
+                ..Default::default()
+            }
+        }
+
+        fn supports_language(&self, _source_lang: &str, target_lang: &str) -> bool {
+            target_lang == "en"
+        }
+
+        async fn translate(
+            &self,
+            texts: Vec<&str>,
+            _source_lang: &str,
+            _target_lang: &str,
+        ) -> Result<Vec<String>, Box<dyn Error>> {
+            Ok(texts.iter().map(|text| text.to_uppercase()).collect())
+        }
+    }
+
+    #[test]
+    fn test_default_registry() {
+        let registry = TranslatorRegistry::default();
+        assert_eq!(registry.names(), vec!["deepl", "llm", "whisper"]);
+
+        let config = TranslatorConfig::default();
+        for name in registry.names() {
+            assert_eq!(registry.create(name, &config).unwrap().name(), name);
+        }
+        assert!(registry.create("google", &config).is_err());
+
+        let whisper = registry.create("whisper", &config).unwrap();
+        assert!(whisper.capabilities().speech);
+        assert!(whisper.supports_language("ja", "en"));
+        assert!(!whisper.supports_language("ja", "fr"));
+    }
+
+    #[test]
+    fn test_register_translator() {
+        let mut registry = TranslatorRegistry::new();
+        registry.register("uppercase", |_| Ok(Box::new(UppercaseTranslator)));
+        let translator = registry
+            .create("uppercase", &TranslatorConfig::default())
+            .unwrap();
+        assert!(translator.capabilities().batch);
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let result = rt.block_on(translator.translate(vec!["hello", "world"], "fr", "en"));
+        assert_eq!(result.unwrap(), vec!["HELLO", "WORLD"]);
+    }
+}
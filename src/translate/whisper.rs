@@ -0,0 +1,33 @@
+use super::{Translator, TranslatorCapabilities};
+use async_trait::async_trait;
+use std::error::Error;
+
+/// Whisper.cpp translates the audio into English while transcribing it
+pub struct WhisperTranslator;
+
+#[async_trait(?Send)]
+impl Translator for WhisperTranslator {
+    fn name(&self) -> &str {
+        "whisper"
+    }
+
+    fn capabilities(&self) -> TranslatorCapabilities {
+        TranslatorCapabilities {
+            speech: true,
+            ..Default::default()
+        }
+    }
+
+    fn supports_language(&self, _source_lang: &str, target_lang: &str) -> bool {
+        target_lang == "en"
+    }
+
+    async fn translate(
+        &self,
+        _texts: Vec<&str>,
+        _source_lang: &str,
+        _target_lang: &str,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        Err("Whisper only translates the audio while transcribing it".into())
+    }
+}
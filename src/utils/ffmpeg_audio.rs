@@ -2,12 +2,16 @@ use ffmpeg::format::input;
 use ffmpeg::format::sample::Type as SampleType;
 use ffmpeg::media::Type;
 use ffmpeg::software::resampling::context::Context as Resampler;
+use ffmpeg::util::channel_layout::ChannelLayout;
 use ffmpeg_next::{
-    self as ffmpeg, format,
+    self as ffmpeg, decoder, format,
     frame::{self, Audio},
-    util,
+    Rational,
 };
 
+use super::ffmpeg_stream::{list_streams, select_stream, StreamSelector};
+use super::time::TimeRange;
+
 fn convert_to_f32_audio_sample(samples: Vec<u8>, format: format::Sample) -> f32 {
     match format {
         ffmpeg::format::Sample::U8(_) => {
@@ -74,11 +78,67 @@ fn retrieve_f32_audio_samples(decoded: &frame::Audio, plane: usize) -> Vec<f32>
     converted_samples
 }
 
-// Extract audio from video using ffmpeg-next
-pub fn extract_audio_from_video(video_path: &str, audio_path: &str, output_sample_rate: u32) {
+// Extract the audio in the given time range using ffmpeg-next, the output starts at the range start
+pub fn extract_audio_segment_from_video(
+    video_path: &str,
+    audio_path: &str,
+    output_sample_rate: u32,
+    range: &TimeRange,
+    selector: &StreamSelector,
+) -> Result<(), ffmpeg::Error> {
+    // Prepare wav writer
+    let mut writer = hound::WavWriter::create(
+        audio_path,
+        hound::WavSpec {
+            channels: 1,
+            sample_rate: output_sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        },
+    )
+    .unwrap();
+
+    decode_audio_chunks_from_video(video_path, output_sample_rate, range, selector, |samples| {
+        for &sample in samples {
+            writer.write_sample(sample).unwrap();
+        }
+    })?;
+
+    // Close the writer
+    writer.finalize().unwrap();
+    Ok(())
+}
+
+/// Decode the audio in the given time range into mono f32 samples, without any temp file
+pub fn decode_audio_segment_from_video(
+    video_path: &str,
+    output_sample_rate: u32,
+    range: &TimeRange,
+    selector: &StreamSelector,
+) -> Result<Vec<f32>, ffmpeg::Error> {
+    let mut samples = Vec::new();
+    decode_audio_chunks_from_video(video_path, output_sample_rate, range, selector, |chunk| {
+        samples.extend_from_slice(chunk)
+    })?;
+    Ok(samples)
+}
+
+/// Decode the audio in the given time range, `on_samples` receives the mono f32 samples
+/// chunk by chunk as they are decoded
+pub fn decode_audio_chunks_from_video<F: FnMut(&[f32])>(
+    video_path: &str,
+    output_sample_rate: u32,
+    range: &TimeRange,
+    selector: &StreamSelector,
+    on_samples: F,
+) -> Result<(), ffmpeg::Error> {
     ffmpeg::init().unwrap();
 
-    let mut ictx = input(video_path).unwrap();
+    let mut ictx = input(video_path)?;
+    let streams = list_streams(&ictx, Type::Audio);
+    for stream in streams.iter() {
+        println!("Audio stream: {}", stream);
+    }
     println!(
         "Number of streams: {}, number of chapters: {}",
         ictx.nb_streams(),
@@ -89,71 +149,181 @@ pub fn extract_audio_from_video(video_path: &str, audio_path: &str, output_sampl
     println!("Metadata: {:?}", ictx.metadata());
     let format = ictx.format();
     println!("Format: {} {}", format.name(), format.description());
-    let input = ictx
-        .streams()
-        .best(Type::Audio)
-        .ok_or(ffmpeg::Error::StreamNotFound)
-        .unwrap();
+    let stream_index = match selector {
+        StreamSelector::Best => ictx.streams().best(Type::Audio).map(|s| s.index()),
+        _ => select_stream(&streams, selector),
+    }
+    .ok_or(ffmpeg::Error::StreamNotFound)?;
+    let input = ictx.stream(stream_index).unwrap();
     println!("Input: {:?}", input.index());
     println!("Input codec: {}", input.parameters().id().name());
-    let context_decoder =
-        ffmpeg::codec::context::Context::from_parameters(input.parameters()).unwrap();
+    let time_base = input.time_base();
+    let context_decoder = ffmpeg::codec::context::Context::from_parameters(input.parameters())?;
 
     // Prepare decoder
-    let mut decoder = context_decoder.decoder().audio().unwrap();
+    let mut decoder = context_decoder.decoder().audio()?;
 
-    // Prepare wav writer
-    let mut writer = hound::WavWriter::create(
-        audio_path,
-        hound::WavSpec {
-            channels: 1,
-            sample_rate: output_sample_rate,
-            bits_per_sample: 32,
-            sample_format: hound::SampleFormat::Float,
-        },
-    )
-    .unwrap();
+    if range.start > 0.0 {
+        // Seek to the closest position before the start, the rest is dropped while decoding
+        let timestamp = (range.start as f64 * ffmpeg::ffi::AV_TIME_BASE as f64) as i64;
+        ictx.seek(timestamp, ..timestamp)?;
+    }
 
+    let mut receiver = SampleReceiver {
+        resampler: MonoResampler::new(output_sample_rate),
+        time_base,
+        range,
+        position: None,
+        finished: false,
+        on_samples,
+    };
     for (stream, packet) in ictx.packets() {
-        if stream.index() == 1 {
-            // let mut decoded = Video::empty();
-            // decoder.send_packet(&packet).unwrap();
-            let mut decoded = frame::Audio::empty();
-            decoder.send_packet(&packet).unwrap();
-            while decoder.receive_frame(&mut decoded).is_ok() {
-                // Create resampler
-                let mut resampler = Resampler::get(
-                    decoded.format(),
-                    decoded.channel_layout(),
-                    decoded.rate(),
-                    format::Sample::F32(SampleType::Planar),
-                    util::channel_layout::ChannelLayout::MONO,
-                    output_sample_rate,
-                )
-                .unwrap();
+        if receiver.finished {
+            break;
+        }
+        if stream.index() == stream_index {
+            decoder.send_packet(&packet)?;
+            receiver.receive_frames(&mut decoder)?;
+        }
+    }
+    if !receiver.finished {
+        decoder.send_eof()?;
+        receiver.receive_frames(&mut decoder)?;
+    }
+    receiver.flush()
+}
+
+/// Resample decoded frames to mono f32 samples with a single resampling context
+///
+/// The context is drained and rebuilt only if the input format, layout or rate changes.
+struct MonoResampler {
+    context: Option<Resampler>,
+    output_sample_rate: u32,
+}
+
+impl MonoResampler {
+    fn new(output_sample_rate: u32) -> MonoResampler {
+        MonoResampler {
+            context: None,
+            output_sample_rate,
+        }
+    }
+
+    /// Output frame with room for the converted input and the samples buffered by the context
+    fn output_frame(context: &Resampler, input_samples: usize) -> Audio {
+        let output = context.output();
+        let samples = (input_samples as u64 * output.rate as u64)
+            .div_ceil(context.input().rate as u64) as usize;
+        Audio::new(output.format, samples + 256, output.channel_layout)
+    }
+
+    fn run(&mut self, decoded: &mut frame::Audio) -> Result<Vec<f32>, ffmpeg::Error> {
+        // Some decoders leave the layout unspecified, assume the default one for the channels
+        if decoded.channel_layout().is_empty() {
+            decoded.set_channel_layout(ChannelLayout::default(decoded.channels() as i32));
+        }
+
+        let mut samples = Vec::new();
+        let changed = match &self.context {
+            Some(context) => {
+                let input = context.input();
+                input.format != decoded.format()
+                    || input.channel_layout != decoded.channel_layout()
+                    || input.rate != decoded.rate()
+            }
+            None => true,
+        };
+        if changed {
+            samples = self.flush()?;
+            self.context = Some(Resampler::get(
+                decoded.format(),
+                decoded.channel_layout(),
+                decoded.rate(),
+                format::Sample::F32(SampleType::Planar),
+                ChannelLayout::MONO,
+                self.output_sample_rate,
+            )?);
+        }
+
+        let context = self.context.as_mut().unwrap();
+        let mut output_frame = Self::output_frame(context, decoded.samples());
+        context.run(decoded, &mut output_frame)?;
+        // Mono planar output, a single plane
+        samples.extend(retrieve_f32_audio_samples(&output_frame, 0));
+        Ok(samples)
+    }
 
-                // Create output frame
-                let mut output_frame = Audio::new(
-                    resampler.output().format,
-                    decoded.samples(),
-                    resampler.output().channel_layout,
-                );
-
-                // Currently only support one plane
-                let plane = 0;
-                // Convert to the given sample rate
-                resampler.run(&decoded, &mut output_frame).unwrap();
-                let resampled_samples = retrieve_f32_audio_samples(&output_frame, plane);
-
-                for sample in resampled_samples {
-                    writer.write_sample(sample).unwrap();
+    /// Drain the samples still buffered in the context
+    fn flush(&mut self) -> Result<Vec<f32>, ffmpeg::Error> {
+        let mut samples = Vec::new();
+        if let Some(context) = self.context.as_mut() {
+            loop {
+                let mut output_frame = Self::output_frame(context, 0);
+                context.flush(&mut output_frame)?;
+                if output_frame.samples() == 0 {
+                    break;
                 }
-                writer.flush().unwrap();
+                samples.extend(retrieve_f32_audio_samples(&output_frame, 0));
             }
         }
+        Ok(samples)
+    }
+}
+
+struct SampleReceiver<'a, F> {
+    resampler: MonoResampler,
+    time_base: Rational,
+    range: &'a TimeRange,
+    /// Time of the next output sample, from the timestamp of the first decoded frame
+    position: Option<f64>,
+    finished: bool,
+    on_samples: F,
+}
+
+impl<F: FnMut(&[f32])> SampleReceiver<'_, F> {
+    fn receive_frames(&mut self, decoder: &mut decoder::Audio) -> Result<(), ffmpeg::Error> {
+        let mut decoded = frame::Audio::empty();
+        while !self.finished && decoder.receive_frame(&mut decoded).is_ok() {
+            let frame_start = decoded
+                .timestamp()
+                .or(decoded.pts())
+                .map(|ts| ts as f64 * f64::from(self.time_base))
+                .unwrap_or(0.0);
+            if self.range.end.is_some_and(|end| frame_start >= end as f64) {
+                self.finished = true;
+                break;
+            }
+            if self.position.is_none() {
+                self.position = Some(frame_start);
+            }
+
+            let samples = self.resampler.run(&mut decoded)?;
+            self.emit(&samples);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ffmpeg::Error> {
+        let samples = self.resampler.flush()?;
+        self.emit(&samples);
+        Ok(())
+    }
+
+    /// Only keep the samples in the range
+    fn emit(&mut self, samples: &[f32]) {
+        let rate = self.resampler.output_sample_rate as f64;
+        let start = self.position.unwrap_or(0.0);
+        self.position = Some(start + samples.len() as f64 / rate);
+
+        let skip = ((self.range.start as f64 - start).max(0.0) * rate).round() as usize;
+        let keep = self.range.end.map_or(samples.len(), |end| {
+            ((end as f64 - start).max(0.0) * rate).round() as usize
+        });
+        let keep = keep.min(samples.len());
+        if skip < keep {
+            (self.on_samples)(&samples[skip..keep]);
+        }
     }
-    // Close the writer
-    writer.finalize().unwrap();
 }
 
 #[cfg(test)]
@@ -197,7 +367,160 @@ mod tests {
     fn test_extract_audio_from_video() {
         let (video_path, audio_path) = setup();
 
-        extract_audio_from_video(video_path.as_str(), audio_path.as_str(), 16000);
+        extract_audio_segment_from_video(
+            video_path.as_str(),
+            audio_path.as_str(),
+            16000,
+            &TimeRange::default(),
+            &StreamSelector::Best,
+        )
+        .unwrap();
         assert!(std::path::Path::new(audio_path.as_str()).exists());
     }
+
+    #[test]
+    fn test_extract_audio_segment_from_video() {
+        let (video_path, _) = setup();
+        let audio_path = Path::new("data")
+            .join("utils")
+            .join("audio_segment.wav")
+            .as_os_str()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        extract_audio_segment_from_video(
+            video_path.as_str(),
+            audio_path.as_str(),
+            16000,
+            &TimeRange::new(2.0, Some(5.0)),
+            &StreamSelector::Best,
+        )
+        .unwrap();
+        let reader = hound::WavReader::open(audio_path.as_str()).unwrap();
+        let duration = reader.duration() as f32 / reader.spec().sample_rate as f32;
+        assert!(duration <= 3.0);
+        assert!(duration > 2.5);
+    }
+
+    #[test]
+    fn test_decode_audio_segment_from_video() {
+        let (video_path, _) = setup();
+        let audio_path = Path::new("data")
+            .join("utils")
+            .join("audio_decoded.wav")
+            .as_os_str()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        extract_audio_segment_from_video(
+            video_path.as_str(),
+            audio_path.as_str(),
+            16000,
+            &TimeRange::default(),
+            &StreamSelector::Best,
+        )
+        .unwrap();
+        let wav_samples: Vec<f32> = hound::WavReader::open(audio_path.as_str())
+            .unwrap()
+            .into_samples::<f32>()
+            .map(|x| x.unwrap())
+            .collect();
+
+        let samples = decode_audio_segment_from_video(
+            video_path.as_str(),
+            16000,
+            &TimeRange::default(),
+            &StreamSelector::Best,
+        )
+        .unwrap();
+        assert_eq!(samples, wav_samples);
+    }
+
+    /// Write a stereo 16-bit sine tone, decoded by ffmpeg as a regular input
+    fn write_tone(path: &str, sample_rate: u32, duration: f32) {
+        std::fs::create_dir_all(Path::new(path).parent().unwrap()).unwrap();
+        let mut writer = hound::WavWriter::create(
+            path,
+            hound::WavSpec {
+                channels: 2,
+                sample_rate,
+                bits_per_sample: 16,
+                sample_format: hound::SampleFormat::Int,
+            },
+        )
+        .unwrap();
+        for i in 0..(sample_rate as f32 * duration) as u32 {
+            let t = i as f32 / sample_rate as f32;
+            let sample = ((2.0 * std::f32::consts::PI * 440.0 * t).sin() * 16384.0) as i16;
+            writer.write_sample(sample).unwrap();
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn test_decode_audio_keeps_duration() {
+        for sample_rate in [44100, 48000, 8000] {
+            let tone_path = Path::new("data")
+                .join("utils")
+                .join(format!("tone_{}.wav", sample_rate))
+                .as_os_str()
+                .to_str()
+                .unwrap()
+                .to_string();
+            write_tone(tone_path.as_str(), sample_rate, 2.5);
+
+            let samples = decode_audio_segment_from_video(
+                tone_path.as_str(),
+                16000,
+                &TimeRange::default(),
+                &StreamSelector::Best,
+            )
+            .unwrap();
+            let duration = samples.len() as f32 / 16000.0;
+            assert!(
+                (duration - 2.5).abs() < 0.005,
+                "{} Hz tone decoded to {} s",
+                sample_rate,
+                duration
+            );
+            // The tone is still there at the end, not dropped with the resampler delay
+            let tail = &samples[samples.len() - 160..];
+            assert!(tail.iter().any(|sample| sample.abs() > 0.1));
+        }
+    }
+
+    #[test]
+    fn test_extract_audio_from_missing_stream() {
+        let (video_path, _) = setup();
+        let audio_path = Path::new("data")
+            .join("utils")
+            .join("audio_missing.wav")
+            .as_os_str()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let result = extract_audio_segment_from_video(
+            video_path.as_str(),
+            audio_path.as_str(),
+            16000,
+            &TimeRange::default(),
+            &StreamSelector::Index(5),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_decode_audio_from_missing_video() {
+        let result = decode_audio_segment_from_video(
+            "data/utils/missing.mp4",
+            16000,
+            &TimeRange::default(),
+            &StreamSelector::Best,
+        );
+        assert!(result.is_err());
+    }
 }
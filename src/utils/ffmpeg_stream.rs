@@ -0,0 +1,206 @@
+use ffmpeg::format::context::Input;
+use ffmpeg::media::Type;
+use ffmpeg_next as ffmpeg;
+
+/// How to pick the audio or subtitle stream to extract from a container
+pub enum StreamSelector {
+    /// The first suitable stream
+    Best,
+    /// Absolute stream index in the container (as printed by `list_streams`)
+    Index(usize),
+    /// Language tag in the stream metadata (e.g. "jpn", "ja")
+    Language(String),
+}
+
+#[derive(Debug)]
+pub struct StreamInfo {
+    pub index: usize,
+    pub codec: ffmpeg::codec::Id,
+    pub language: Option<String>,
+    pub title: Option<String>,
+}
+
+impl std::fmt::Display for StreamInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "#{} {}", self.index, self.codec.name())?;
+        if let Some(language) = &self.language {
+            write!(f, " [{}]", language)?;
+        }
+        if let Some(title) = &self.title {
+            write!(f, " \"{}\"", title)?;
+        }
+        Ok(())
+    }
+}
+
+/// ISO 639-1, ISO 639-2 bibliographic (as used by Matroska) and terminology codes
+const ISO639_CODES: &[(&str, &str, &str)] = &[
+    ("ar", "ara", "ara"),
+    ("cs", "cze", "ces"),
+    ("de", "ger", "deu"),
+    ("el", "gre", "ell"),
+    ("en", "eng", "eng"),
+    ("es", "spa", "spa"),
+    ("fa", "per", "fas"),
+    ("fr", "fre", "fra"),
+    ("hi", "hin", "hin"),
+    ("id", "ind", "ind"),
+    ("it", "ita", "ita"),
+    ("ja", "jpn", "jpn"),
+    ("ko", "kor", "kor"),
+    ("nl", "dut", "nld"),
+    ("pl", "pol", "pol"),
+    ("pt", "por", "por"),
+    ("ro", "rum", "ron"),
+    ("ru", "rus", "rus"),
+    ("th", "tha", "tha"),
+    ("tr", "tur", "tur"),
+    ("uk", "ukr", "ukr"),
+    ("vi", "vie", "vie"),
+    ("zh", "chi", "zho"),
+];
+
+/// Codes of a language given by any of its ISO 639-1 or ISO 639-2 codes, e.g. "zh-TW" or "deu"
+fn iso639_codes(language: &str) -> Option<(&'static str, &'static str, &'static str)> {
+    let language = language.trim().to_lowercase();
+    // Regional variants like "pt-BR" are the same language
+    let language = match language.split(['-', '_']).next().unwrap_or("") {
+        "tw" => "zh",
+        language => language,
+    };
+    ISO639_CODES
+        .iter()
+        .copied()
+        .find(|(part1, bibliographic, terminology)| {
+            [*part1, *bibliographic, *terminology].contains(&language)
+        })
+}
+
+/// ISO 639-2 (bibliographic, as used by Matroska) code of a language, unknown ones are kept
+pub(crate) fn iso639_2_language(language: &str) -> String {
+    iso639_codes(language).map_or(language.to_string(), |(_, bibliographic, _)| {
+        bibliographic.to_string()
+    })
+}
+
+/// Containers usually carry ISO 639-2 ("jpn", "ger" or "deu") while the CLI uses ISO 639-1
+pub(crate) fn language_matches(stream_language: &str, wanted: &str) -> bool {
+    match (iso639_codes(stream_language), iso639_codes(wanted)) {
+        (Some(stream_codes), Some(wanted_codes)) => stream_codes == wanted_codes,
+        _ => stream_language.trim().eq_ignore_ascii_case(wanted.trim()),
+    }
+}
+
+/// List the streams of the given type in an opened video, with their codec and language tags
+pub fn list_streams(ictx: &Input, medium: Type) -> Vec<StreamInfo> {
+    ictx.streams()
+        .filter(|stream| stream.parameters().medium() == medium)
+        .map(|stream| StreamInfo {
+            index: stream.index(),
+            codec: stream.parameters().id(),
+            language: stream.metadata().get("language").map(|s| s.to_string()),
+            title: stream.metadata().get("title").map(|s| s.to_string()),
+        })
+        .collect()
+}
+
+pub(crate) fn select_stream(streams: &[StreamInfo], selector: &StreamSelector) -> Option<usize> {
+    match selector {
+        StreamSelector::Best => streams.first().map(|s| s.index),
+        StreamSelector::Index(index) => streams.iter().find(|s| s.index == *index).map(|s| s.index),
+        StreamSelector::Language(language) => streams
+            .iter()
+            .find(|s| {
+                s.language
+                    .as_deref()
+                    .is_some_and(|l| language_matches(l, language))
+            })
+            .map(|s| s.index),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iso639_2_language() {
+        assert_eq!(iso639_2_language("de"), "ger");
+        assert_eq!(iso639_2_language("deu"), "ger");
+        assert_eq!(iso639_2_language("zh-TW"), "chi");
+        assert_eq!(iso639_2_language("cs"), "cze");
+        // Already ISO 639-2
+        assert_eq!(iso639_2_language("jpn"), "jpn");
+        assert_eq!(iso639_2_language("tlh"), "tlh");
+    }
+
+    #[test]
+    fn test_language_matches() {
+        assert!(language_matches("jpn", "ja"));
+        assert!(language_matches("fra", "fre"));
+        assert!(!language_matches("eng", "ja"));
+        assert!(!language_matches("und", "ja"));
+        assert!(language_matches("und", "UND"));
+    }
+
+    #[test]
+    fn test_select_stream() {
+        let streams = vec![
+            StreamInfo {
+                index: 2,
+                codec: ffmpeg::codec::Id::SUBRIP,
+                language: Some("eng".to_string()),
+                title: None,
+            },
+            StreamInfo {
+                index: 3,
+                codec: ffmpeg::codec::Id::ASS,
+                language: Some("jpn".to_string()),
+                title: Some("Japanese".to_string()),
+            },
+        ];
+
+        assert_eq!(select_stream(&streams, &StreamSelector::Best), Some(2));
+        assert_eq!(select_stream(&streams, &StreamSelector::Index(3)), Some(3));
+        assert_eq!(select_stream(&streams, &StreamSelector::Index(0)), None);
+        assert_eq!(
+            select_stream(&streams, &StreamSelector::Language("ja".to_string())),
+            Some(3)
+        );
+        assert_eq!(
+            select_stream(&streams, &StreamSelector::Language("fr".to_string())),
+            None
+        );
+    }
+
+    #[test]
+    fn test_select_stream_by_language_code() {
+        // Codes sharing no prefix with the ISO 639-1 ones, in both ISO 639-2 forms
+        let stream = |index: usize, language: &str| StreamInfo {
+            index,
+            codec: ffmpeg::codec::Id::SUBRIP,
+            language: Some(language.to_string()),
+            title: None,
+        };
+        let streams = vec![
+            stream(2, "ger"),
+            stream(3, "zho"),
+            stream(4, "deu"),
+            stream(5, "chi"),
+        ];
+        let select = |language: &str| {
+            select_stream(&streams, &StreamSelector::Language(language.to_string()))
+        };
+
+        assert_eq!(select("de"), Some(2));
+        assert_eq!(select("deu"), Some(2));
+        assert_eq!(select("zh"), Some(3));
+        assert_eq!(select("zh-TW"), Some(3));
+        assert_eq!(select("chi"), Some(3));
+        assert_eq!(select("fr"), None);
+        assert_eq!(
+            select_stream(&streams[2..], &StreamSelector::Language("ger".to_string())),
+            Some(4)
+        );
+    }
+}
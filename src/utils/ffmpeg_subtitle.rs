@@ -0,0 +1,201 @@
+use ffmpeg::format::input;
+use ffmpeg::media::Type;
+use ffmpeg_next::{self as ffmpeg, codec::subtitle::Rect};
+
+use super::ffmpeg_stream::{list_streams, select_stream, StreamInfo, StreamSelector};
+use super::Subtitle;
+
+fn is_text_subtitle_codec(id: ffmpeg::codec::Id) -> bool {
+    matches!(
+        id,
+        ffmpeg::codec::Id::SUBRIP
+            | ffmpeg::codec::Id::SRT
+            | ffmpeg::codec::Id::ASS
+            | ffmpeg::codec::Id::SSA
+            | ffmpeg::codec::Id::MOV_TEXT
+            | ffmpeg::codec::Id::WEBVTT
+            | ffmpeg::codec::Id::TEXT
+    )
+}
+
+pub(crate) fn is_bitmap_subtitle_codec(id: ffmpeg::codec::Id) -> bool {
+    matches!(
+        id,
+        ffmpeg::codec::Id::HDMV_PGS_SUBTITLE
+            | ffmpeg::codec::Id::DVD_SUBTITLE
+            | ffmpeg::codec::Id::DVB_SUBTITLE
+            | ffmpeg::codec::Id::XSUB
+    )
+}
+
+/// Convert an ASS dialogue event (as produced by libavcodec text decoders) to plain text
+///
+/// The decoded event looks like `ReadOrder,Layer,Style,Name,MarginL,MarginR,MarginV,Effect,Text`
+/// (or `Layer,Start,End,Style,...` for a full `Dialogue:` line), the text itself might contain
+/// override blocks (`{\i1}`) and hard line breaks (`\N`).
+fn ass_event_to_text(event: &str) -> String {
+    let text = match event.strip_prefix("Dialogue:") {
+        Some(dialogue) => dialogue.trim_start().splitn(10, ',').nth(9),
+        None => event.splitn(9, ',').nth(8),
+    }
+    .unwrap_or(event);
+    ass_text_to_plain(text)
+}
+
+/// Style name of an ASS dialogue event, in the formats of `ass_event_to_text`
+fn ass_event_style(event: &str) -> Option<String> {
+    match event.strip_prefix("Dialogue:") {
+        Some(dialogue) => dialogue.trim_start().split(',').nth(3),
+        None => event.split(',').nth(2),
+    }
+    .map(|style| style.trim().to_string())
+    .filter(|style| !style.is_empty())
+}
+
+/// Remove the override blocks of an ASS text and convert its hard line breaks and spaces
+pub(crate) fn ass_text_to_plain(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut in_override = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => in_override = true,
+            '}' if in_override => in_override = false,
+            _ if in_override => {}
+            '\\' => match chars.peek() {
+                Some('N') | Some('n') => {
+                    chars.next();
+                    plain.push('\n');
+                }
+                Some('h') => {
+                    chars.next();
+                    plain.push(' ');
+                }
+                _ => plain.push(c),
+            },
+            _ => plain.push(c),
+        }
+    }
+    plain.trim().to_string()
+}
+
+/// Extract the text subtitles of a stream in the video using ffmpeg-next
+pub fn extract_subtitles_from_video(
+    video_path: &str,
+    selector: &StreamSelector,
+) -> Result<Vec<Subtitle>, ffmpeg::Error> {
+    ffmpeg::init().unwrap();
+
+    let mut ictx = input(video_path)?;
+    let streams = list_streams(&ictx, Type::Subtitle);
+    for stream in streams.iter() {
+        println!("Subtitle stream: {}", stream);
+    }
+    let text_streams: Vec<StreamInfo> = streams
+        .into_iter()
+        .filter(|s| is_text_subtitle_codec(s.codec))
+        .collect();
+    let stream_index =
+        select_stream(&text_streams, selector).ok_or(ffmpeg::Error::StreamNotFound)?;
+
+    let stream = ictx
+        .stream(stream_index)
+        .ok_or(ffmpeg::Error::StreamNotFound)?;
+    let time_base = stream.time_base();
+    // The decoders of the other codecs name the style of every event "Default"
+    let keep_styles = matches!(
+        stream.parameters().id(),
+        ffmpeg::codec::Id::ASS | ffmpeg::codec::Id::SSA
+    );
+    println!("Input: {:?}", stream_index);
+    println!("Input codec: {}", stream.parameters().id().name());
+
+    let context_decoder = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?;
+    let mut decoder = context_decoder.decoder();
+    decoder.set_packet_time_base(time_base);
+    let mut decoder = decoder.subtitle()?;
+
+    let mut subtitles = Vec::new();
+    for (stream, packet) in ictx.packets() {
+        if stream.index() != stream_index {
+            continue;
+        }
+        let mut decoded = ffmpeg::Subtitle::new();
+        if !decoder.decode(&packet, &mut decoded)? {
+            continue;
+        }
+
+        let pts = match packet.pts().or(packet.dts()) {
+            Some(pts) => pts as f64 * f64::from(time_base),
+            None => continue,
+        };
+        let start = pts + decoded.start() as f64 / 1000.;
+        let end = if packet.duration() > 0 {
+            pts + packet.duration() as f64 * f64::from(time_base)
+        } else {
+            pts + decoded.end() as f64 / 1000.
+        };
+
+        let text = decoded
+            .rects()
+            .filter_map(|rect| match rect {
+                Rect::Text(text) => Some(text.get().trim().to_string()),
+                Rect::Ass(ass) => Some(ass_event_to_text(ass.get())),
+                _ => None,
+            })
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<String>>()
+            .join("\n");
+        if text.is_empty() {
+            continue;
+        }
+
+        let style = decoded.rects().find_map(|rect| match rect {
+            Rect::Ass(ass) if keep_styles => ass_event_style(ass.get()),
+            _ => None,
+        });
+        subtitles.push(Subtitle {
+            style,
+            ..Subtitle::new(start as f32, end as f32, text)
+        });
+    }
+
+    Ok(subtitles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ass_event_to_text() {
+        assert_eq!(
+            ass_event_to_text("0,0,Default,,0,0,0,,{\\i1}Hello{\\i0},\\Nworld!"),
+            "Hello,\nworld!"
+        );
+        assert_eq!(
+            ass_event_to_text("Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,こんにちは"),
+            "こんにちは"
+        );
+    }
+
+    #[test]
+    fn test_ass_event_style() {
+        assert_eq!(
+            ass_event_style("0,0,Sign,,0,0,0,,Station").as_deref(),
+            Some("Sign")
+        );
+        assert_eq!(
+            ass_event_style("Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,こんにちは")
+                .as_deref(),
+            Some("Default")
+        );
+        assert_eq!(ass_event_style("0,0,,,0,0,0,,Hello"), None);
+    }
+
+    #[test]
+    fn test_extract_subtitles_from_missing_video() {
+        let result = extract_subtitles_from_video("data/utils/missing.mkv", &StreamSelector::Best);
+        assert!(result.is_err());
+    }
+}
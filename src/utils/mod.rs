@@ -1,14 +1,92 @@
+use std::ops::Range;
+
 pub mod ffmpeg_audio;
+pub mod ffmpeg_stream;
+pub mod ffmpeg_subtitle;
+pub mod overrides;
+pub mod resegment;
+pub mod subtitle_file;
+pub mod time;
+pub mod vad;
 pub mod whisper_state;
 
+/// A timed word of a subtitle, e.g. from the whisper token timestamps
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Word {
+    pub start: f32,
+    pub end: f32,
+    pub text: String,
+    /// Lowest probability of the recognized tokens of the word, between 0 and 1
+    pub probability: f32,
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct Subtitle {
     pub start: f32,
     pub end: f32,
     pub text: String,
+    /// Named style to render the subtitle with (if supported by the exporter)
+    pub style: Option<String>,
+    /// Words of the text with their own timing, empty if unknown or after translation
+    pub words: Vec<Word>,
+    /// Text before the translation, `None` until translated
+    pub original_text: Option<String>,
 }
 
 impl Subtitle {
     pub fn new(start: f32, end: f32, text: String) -> Subtitle {
-        Subtitle { start, end, text }
+        Subtitle {
+            start,
+            end,
+            text,
+            style: None,
+            words: Vec::new(),
+            original_text: None,
+        }
+    }
+
+    /// Move the subtitle and its words by `offset` seconds
+    pub fn shift(&mut self, offset: f32) {
+        self.start += offset;
+        self.end += offset;
+        for word in self.words.iter_mut() {
+            word.start += offset;
+            word.end += offset;
+        }
+    }
+}
+
+/// Whether the character is written without spaces between words (Chinese, Japanese, Korean)
+pub(crate) fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3000..=0x30ff // CJK punctuation, Hiragana, Katakana
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xac00..=0xd7af // Hangul
+        | 0xff00..=0xffef)
+}
+
+/// Byte ranges of the words of the text (each character for CJK), with the spaces after them
+pub(crate) fn split_words(text: &str) -> Vec<Range<usize>> {
+    let mut words: Vec<Range<usize>> = Vec::new();
+    let mut after_space = true;
+    let mut previous_cjk = false;
+    for (i, c) in text.char_indices() {
+        let end = i + c.len_utf8();
+        if c.is_whitespace() {
+            if let Some(word) = words.last_mut() {
+                word.end = end;
+            }
+            after_space = true;
+            continue;
+        }
+        let cjk = is_cjk(c);
+        match words.last_mut() {
+            Some(word) if !after_space && !cjk && !previous_cjk => word.end = end,
+            _ => words.push(i..end),
+        }
+        after_space = false;
+        previous_cjk = cjk;
     }
+    words
 }
@@ -0,0 +1,58 @@
+/// Parse the value of a field, `kind` names what is overridden in the error (e.g. "style")
+pub(crate) fn parse_field<T: std::str::FromStr>(
+    kind: &str,
+    key: &str,
+    value: &str,
+) -> Result<T, String> {
+    value
+        .trim()
+        .parse::<T>()
+        .map_err(|_| format!("Invalid value {} for {} field {}", value, kind, key))
+}
+
+/// Split comma separated `key=value` overrides (ASS styles, re-segmentation profiles) and set
+/// each of them with `set`
+pub(crate) fn apply_overrides(
+    kind: &str,
+    overrides: &str,
+    mut set: impl FnMut(&str, &str) -> Result<(), String>,
+) -> Result<(), String> {
+    for item in overrides.split(',').filter(|item| !item.trim().is_empty()) {
+        let (key, value) = item
+            .split_once('=')
+            .ok_or(format!("Invalid {} override {}", kind, item))?;
+        set(key, value)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_overrides() {
+        let mut fields = Vec::new();
+        apply_overrides("style", "Fontsize=60, ,Name=Sign", |key, value| {
+            fields.push((key.to_string(), parse_field::<String>("style", key, value)?));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            fields,
+            vec![
+                ("Fontsize".to_string(), "60".to_string()),
+                ("Name".to_string(), "Sign".to_string())
+            ]
+        );
+
+        assert_eq!(
+            apply_overrides("style", "Fontsize", |_, _| Ok(())),
+            Err("Invalid style override Fontsize".to_string())
+        );
+        assert_eq!(
+            parse_field::<u32>("profile", "max_lines", "two"),
+            Err("Invalid value two for profile field max_lines".to_string())
+        );
+    }
+}
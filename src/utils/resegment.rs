@@ -0,0 +1,454 @@
+use super::overrides;
+use super::{is_cjk, split_words, Subtitle, Word};
+
+/// Punctuation ending a clause, the best places to end a line or a subtitle
+const CLAUSE_ENDS: &[char] = &[
+    '.', ',', '!', '?', ';', ':', '…', '、', '。', '，', '！', '？',
+];
+/// A line is better broken before these words
+const BREAK_BEFORE: &[&str] = &[
+    "and", "but", "or", "because", "so", "that", "which", "who", "when", "if", "to", "of", "in",
+    "on", "at", "for", "with", "from",
+];
+/// A line should not end with these words
+const NO_BREAK_AFTER: &[&str] = &[
+    "a", "an", "the", "my", "your", "his", "her", "its", "our", "their", "this", "these", "those",
+    "mr.", "mrs.", "dr.",
+];
+/// Japanese particles ending a phrase
+const CJK_BREAK_AFTER: &[&str] = &["は", "が", "を", "に", "で", "と", "も", "へ", "や"];
+/// Longer pauses keep the subtitles apart
+const MAX_MERGE_GAP: f32 = 1.0;
+
+/// Readability limits of the subtitles, widths count CJK characters for two
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReadabilityProfile {
+    pub max_chars_per_line: usize,
+    pub max_lines: usize,
+    /// Maximum reading speed, in width per second
+    pub max_chars_per_second: f32,
+    pub min_duration: f32,
+    pub max_duration: f32,
+    /// Minimum time between two subtitles
+    pub min_gap: f32,
+}
+
+impl ReadabilityProfile {
+    /// Netflix-like timed text style guide
+    pub fn netflix() -> ReadabilityProfile {
+        ReadabilityProfile {
+            max_chars_per_line: 42,
+            max_lines: 2,
+            max_chars_per_second: 17.0,
+            min_duration: 0.833,
+            max_duration: 7.0,
+            min_gap: 0.083,
+        }
+    }
+
+    /// BBC-like subtitle guidelines
+    pub fn bbc() -> ReadabilityProfile {
+        ReadabilityProfile {
+            max_chars_per_line: 37,
+            max_lines: 2,
+            max_chars_per_second: 15.0,
+            min_duration: 1.0,
+            max_duration: 7.0,
+            min_gap: 0.04,
+        }
+    }
+
+    /// A profile by name, "custom" starts from the Netflix-like one and is meant to be overridden
+    pub fn from_name(name: &str) -> Result<ReadabilityProfile, String> {
+        match name {
+            "netflix" | "custom" => Ok(ReadabilityProfile::netflix()),
+            "bbc" => Ok(ReadabilityProfile::bbc()),
+            name => Err(format!("Unsupported re-segmentation profile now, {}", name)),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+            overrides::parse_field("profile", key, value)
+        }
+        match key.trim().to_lowercase().as_str() {
+            "max_chars_per_line" => self.max_chars_per_line = parse(key, value)?,
+            "max_lines" => self.max_lines = parse(key, value)?,
+            "max_chars_per_second" => self.max_chars_per_second = parse(key, value)?,
+            "min_duration" => self.min_duration = parse(key, value)?,
+            "max_duration" => self.max_duration = parse(key, value)?,
+            "min_gap" => self.min_gap = parse(key, value)?,
+            _ => return Err(format!("Unknown profile field {}", key)),
+        }
+        Ok(())
+    }
+
+    /// Apply overrides like "max_chars_per_line=32,max_lines=1"
+    pub fn apply_overrides(&mut self, overrides: &str) -> Result<(), String> {
+        overrides::apply_overrides("profile", overrides, |key, value| self.set(key, value))
+    }
+}
+
+/// Display width of the text, full-width CJK characters count for two
+pub(crate) fn text_width(text: &str) -> usize {
+    text.chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if is_cjk(c) { 2 } else { 1 })
+        .sum()
+}
+
+fn ends_clause(text: &str) -> bool {
+    text.trim_end()
+        .chars()
+        .last()
+        .is_some_and(|c| CLAUSE_ENDS.contains(&c))
+}
+
+/// Join two texts, with a space unless written without spaces (CJK)
+fn join_texts(a: &str, b: &str) -> String {
+    let (a, b) = (a.trim_end(), b.trim_start());
+    let cjk = a.chars().last().is_some_and(is_cjk) || b.chars().next().is_some_and(is_cjk);
+    if a.is_empty() || b.is_empty() || cjk {
+        format!("{}{}", a, b)
+    } else {
+        format!("{} {}", a, b)
+    }
+}
+
+/// The words of the subtitle with their timing, interpolated from the text width if unknown
+fn timed_words(subtitle: &Subtitle) -> Vec<Word> {
+    if !subtitle.words.is_empty() {
+        return subtitle.words.clone();
+    }
+    let text = subtitle.text.as_str();
+    let total = text_width(text).max(1) as f32;
+    let duration = subtitle.end - subtitle.start;
+    let mut position = 0;
+    split_words(text)
+        .into_iter()
+        .map(|range| {
+            let width = text_width(&text[range.clone()]);
+            let word = Word {
+                start: subtitle.start + duration * position as f32 / total,
+                end: subtitle.start + duration * (position + width) as f32 / total,
+                text: text[range].to_string(),
+                probability: 1.0,
+            };
+            position += width;
+            word
+        })
+        .collect()
+}
+
+/// Split a subtitle too long to fit the lines or the maximum duration, at clause ends if possible
+fn split_subtitle(subtitle: &Subtitle, profile: &ReadabilityProfile) -> Vec<Subtitle> {
+    let capacity = profile.max_chars_per_line * profile.max_lines;
+    if text_width(&subtitle.text) <= capacity
+        && subtitle.end - subtitle.start <= profile.max_duration
+    {
+        return vec![subtitle.clone()];
+    }
+
+    let mut pieces: Vec<Vec<Word>> = Vec::new();
+    let mut current: Vec<Word> = Vec::new();
+    for word in timed_words(subtitle) {
+        if let Some(first) = current.first() {
+            let text: String = current.iter().map(|w| w.text.as_str()).collect();
+            if text_width((text + &word.text).trim()) > capacity
+                || word.end - first.start > profile.max_duration
+            {
+                // End the piece after its last clause, unless it would leave a short piece
+                let cut = current
+                    .iter()
+                    .rposition(|w| ends_clause(&w.text))
+                    .map(|i| i + 1)
+                    .filter(|&cut| cut * 2 >= current.len())
+                    .unwrap_or(current.len());
+                let rest = current.split_off(cut);
+                pieces.push(std::mem::replace(&mut current, rest));
+            }
+        }
+        current.push(word);
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+
+    let count = pieces.len();
+    pieces
+        .into_iter()
+        .enumerate()
+        .map(|(i, words)| {
+            let text: String = words.iter().map(|w| w.text.as_str()).collect();
+            Subtitle {
+                start: if i == 0 {
+                    subtitle.start
+                } else {
+                    words[0].start
+                },
+                end: if i == count - 1 {
+                    subtitle.end
+                } else {
+                    words[words.len() - 1].end
+                },
+                text: text.trim().to_string(),
+                words: if subtitle.words.is_empty() {
+                    Vec::new()
+                } else {
+                    words
+                },
+                ..subtitle.clone()
+            }
+        })
+        .collect()
+}
+
+/// Merge the subtitles too short to be read with their neighbour, if the result still fits
+fn merge_subtitles(subtitles: Vec<Subtitle>, profile: &ReadabilityProfile) -> Vec<Subtitle> {
+    let capacity = profile.max_chars_per_line * profile.max_lines;
+    let too_short = |subtitle: &Subtitle| subtitle.end - subtitle.start < profile.min_duration;
+
+    let mut merged: Vec<Subtitle> = Vec::with_capacity(subtitles.len());
+    for subtitle in subtitles {
+        if let Some(last) = merged.last_mut() {
+            let text = join_texts(&last.text, &subtitle.text);
+            if (too_short(last) || too_short(&subtitle))
+                && last.style == subtitle.style
+                && subtitle.start - last.end <= MAX_MERGE_GAP
+                && subtitle.end - last.start <= profile.max_duration
+                && text_width(&text) <= capacity
+            {
+                last.end = subtitle.end;
+                last.text = text;
+                if last.words.is_empty() || subtitle.words.is_empty() {
+                    last.words.clear();
+                } else {
+                    last.words.extend(subtitle.words);
+                }
+                continue;
+            }
+        }
+        merged.push(subtitle);
+    }
+    merged
+}
+
+/// Split the subtitles too long to be read and merge the ones too short, e.g. before translation
+pub fn split_and_merge_subtitles(
+    subtitles: Vec<Subtitle>,
+    profile: &ReadabilityProfile,
+) -> Vec<Subtitle> {
+    let split = subtitles
+        .iter()
+        .flat_map(|subtitle| split_subtitle(subtitle, profile))
+        .collect();
+    merge_subtitles(split, profile)
+}
+
+/// The words of the text, and whether a space follows them
+fn line_words(text: &str) -> Vec<(&str, bool)> {
+    split_words(text)
+        .into_iter()
+        .map(|range| {
+            let word = &text[range];
+            let trimmed = word.trim_end();
+            (trimmed, trimmed.len() < word.len())
+        })
+        .collect()
+}
+
+fn line_text(words: &[(&str, bool)]) -> String {
+    let mut line = String::new();
+    for (i, (word, space)) in words.iter().enumerate() {
+        line.push_str(word);
+        if *space && i + 1 < words.len() {
+            line.push(' ');
+        }
+    }
+    line
+}
+
+/// How good a line break between two words is, the higher the better
+fn break_score(before: &str, after: &str) -> f32 {
+    if ends_clause(before) {
+        1.0
+    } else if CJK_BREAK_AFTER.contains(&before) {
+        0.5
+    } else if NO_BREAK_AFTER.contains(&before.to_lowercase().as_str()) {
+        -1.0
+    } else if BREAK_BEFORE.contains(&after.to_lowercase().as_str()) {
+        0.5
+    } else {
+        0.0
+    }
+}
+
+fn break_words(words: &[(&str, bool)], max_chars: usize, max_lines: usize) -> Vec<String> {
+    let text = line_text(words);
+    let width = text_width(&text);
+    if max_lines <= 1 || width <= max_chars || words.len() < 2 {
+        return vec![text];
+    }
+
+    // Balanced lines, moved to a better break nearby
+    let lines = width.div_ceil(max_chars).min(max_lines);
+    let target = width as f32 / lines as f32;
+    let (best, _) = (1..words.len())
+        .map(|i| {
+            let first = text_width(&line_text(&words[..i]));
+            let rest = text_width(&line_text(&words[i..]));
+            let mut cost = (first as f32 - target).abs()
+                - break_score(words[i - 1].0, words[i].0) * max_chars as f32 / 3.0;
+            if first > max_chars {
+                cost += 1000.0 + (first - max_chars) as f32;
+            }
+            if rest > max_chars * (max_lines - 1) {
+                cost += 1000.0 + (rest - max_chars * (max_lines - 1)) as f32;
+            }
+            (i, cost)
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap();
+
+    let mut lines = vec![line_text(&words[..best])];
+    lines.extend(break_words(&words[best..], max_chars, max_lines - 1));
+    lines
+}
+
+/// Break the text into at most `max_lines` lines of `max_chars` width, at readable points
+pub(crate) fn break_lines(text: &str, max_chars: usize, max_lines: usize) -> String {
+    break_words(&line_words(text), max_chars, max_lines).join("\n")
+}
+
+/// Break the lines of the subtitles and give them time to be read, e.g. after translation
+pub fn fit_subtitles(subtitles: &mut [Subtitle], profile: &ReadabilityProfile) {
+    for subtitle in subtitles.iter_mut() {
+        subtitle.text = break_lines(
+            &subtitle.text,
+            profile.max_chars_per_line,
+            profile.max_lines,
+        );
+        if let Some(original_text) = &subtitle.original_text {
+            subtitle.original_text = Some(break_lines(
+                original_text,
+                profile.max_chars_per_line,
+                profile.max_lines,
+            ));
+        }
+    }
+    for i in 0..subtitles.len() {
+        let next_start = subtitles.get(i + 1).map(|next| next.start);
+        let subtitle = &mut subtitles[i];
+        let required = (text_width(&subtitle.text) as f32 / profile.max_chars_per_second)
+            .max(profile.min_duration)
+            .min(profile.max_duration);
+        let mut end = subtitle.end.max(subtitle.start + required);
+        // Without overlapping the next one
+        if let Some(limit) = next_start.map(|start| start - profile.min_gap) {
+            if limit > subtitle.start {
+                end = end.min(limit);
+            }
+        }
+        subtitle.end = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_width() {
+        assert_eq!(text_width("Hello"), 5);
+        assert_eq!(text_width("日本語です"), 10);
+        assert_eq!(text_width("a\nb"), 2);
+    }
+
+    #[test]
+    fn test_break_lines() {
+        assert_eq!(break_lines("Hello, world!", 42, 2), "Hello, world!");
+        assert_eq!(
+            break_lines(
+                "And so my fellow Americans, ask not what your country can do for you",
+                42,
+                2
+            ),
+            "And so my fellow Americans,\nask not what your country can do for you"
+        );
+        // Not after an article
+        assert_eq!(
+            break_lines("I told you that the house is on fire now", 20, 2),
+            "I told you that\nthe house is on fire now"
+        );
+        assert_eq!(
+            break_lines("今日はいい天気ですね、散歩に行きましょう", 24, 2),
+            "今日はいい天気ですね、\n散歩に行きましょう"
+        );
+    }
+
+    #[test]
+    fn test_split_and_merge_subtitles() {
+        let profile = ReadabilityProfile::netflix();
+        let text = "This is the first sentence of a very long segment. \
+            And this is the second sentence, which keeps going on. \
+            Finally the third one ends here.";
+        let subtitles = split_and_merge_subtitles(
+            vec![
+                Subtitle::new(0.0, 21.0, text.to_string()),
+                Subtitle::new(23.0, 23.4, "Oh".to_string()),
+                Subtitle::new(23.5, 25.0, "Hello there.".to_string()),
+                Subtitle::new(30.0, 30.3, "えっ".to_string()),
+                Subtitle::new(30.4, 31.5, "本当？".to_string()),
+            ],
+            &profile,
+        );
+
+        let split: Vec<&Subtitle> = subtitles.iter().filter(|s| s.end <= 21.0).collect();
+        assert!(split.len() >= 3);
+        assert_eq!(split[0].start, 0.0);
+        assert_eq!(split[split.len() - 1].end, 21.0);
+        for subtitle in split.iter() {
+            assert!(subtitle.end - subtitle.start <= profile.max_duration + 0.01);
+            assert!(text_width(&subtitle.text) <= 84);
+        }
+        let joined: Vec<&str> = split.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(joined.join(" "), text);
+
+        let merged: Vec<&str> = subtitles[split.len()..]
+            .iter()
+            .map(|s| s.text.as_str())
+            .collect();
+        assert_eq!(merged, vec!["Oh Hello there.", "えっ本当？"]);
+    }
+
+    #[test]
+    fn test_fit_subtitles() {
+        let profile = ReadabilityProfile::netflix();
+        let mut subtitles = vec![
+            Subtitle::new(0.0, 0.5, "Hi".to_string()),
+            Subtitle::new(
+                0.6,
+                1.0,
+                "This one needs two seconds to be read".to_string(),
+            ),
+            Subtitle::new(5.0, 6.0, "Bye".to_string()),
+        ];
+        fit_subtitles(&mut subtitles, &profile);
+        assert!((subtitles[0].end - 0.517).abs() < 0.001);
+        assert!((subtitles[1].end - (0.6 + 37.0 / 17.0)).abs() < 0.001);
+        assert!((subtitles[2].end - 6.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_profile_overrides() {
+        let mut profile = ReadabilityProfile::from_name("custom").unwrap();
+        profile
+            .apply_overrides("max_chars_per_line=32,max_lines=1")
+            .unwrap();
+        assert_eq!(profile.max_chars_per_line, 32);
+        assert_eq!(profile.max_lines, 1);
+        assert!(profile.apply_overrides("max_lines=two").is_err());
+        assert!(profile.apply_overrides("speed=12").is_err());
+        assert!(ReadabilityProfile::from_name("itv").is_err());
+    }
+}
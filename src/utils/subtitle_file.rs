@@ -0,0 +1,370 @@
+use std::path::Path;
+
+use encoding_rs::{Encoding, BIG5, EUC_JP, EUC_KR, GBK, SHIFT_JIS, WINDOWS_1252};
+
+use super::ffmpeg_subtitle::ass_text_to_plain;
+use super::time::parse_timestamp;
+use super::Subtitle;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubtitleFileFormat {
+    Srt,
+    Vtt,
+    Ass,
+}
+
+impl SubtitleFileFormat {
+    /// Guess the format from the extension, or from the content if unknown
+    pub fn detect(path: &str, content: &str) -> SubtitleFileFormat {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        match extension.as_deref() {
+            Some("srt") => SubtitleFileFormat::Srt,
+            Some("vtt") => SubtitleFileFormat::Vtt,
+            Some("ass") | Some("ssa") => SubtitleFileFormat::Ass,
+            _ if content.trim_start().starts_with("WEBVTT") => SubtitleFileFormat::Vtt,
+            _ if content.contains("[Events]") => SubtitleFileFormat::Ass,
+            _ => SubtitleFileFormat::Srt,
+        }
+    }
+}
+
+/// Legacy encodings tried for files neither marked by a BOM nor valid UTF-8, by language
+fn legacy_encodings(language: &str) -> Vec<&'static Encoding> {
+    let mut encodings = match language.to_lowercase().as_str() {
+        "ja" => vec![SHIFT_JIS, EUC_JP],
+        "zh" => vec![GBK, BIG5],
+        "tw" | "zh-tw" => vec![BIG5, GBK],
+        "ko" => vec![EUC_KR],
+        _ => Vec::new(),
+    };
+    for encoding in [SHIFT_JIS, GBK, BIG5, EUC_KR] {
+        if !encodings.contains(&encoding) {
+            encodings.push(encoding);
+        }
+    }
+    encodings
+}
+
+/// Decode a subtitle file from its BOM, as UTF-8, or with the legacy encodings of the language
+///
+/// The `encoding` label (e.g. "shift_jis", "gbk") skips the detection.
+pub fn decode_subtitle_bytes(
+    bytes: &[u8],
+    encoding: Option<&str>,
+    language: &str,
+) -> Result<String, String> {
+    if let Some(label) = encoding {
+        let encoding = Encoding::for_label(label.trim().as_bytes())
+            .ok_or(format!("Unknown encoding {}", label))?;
+        return Ok(encoding.decode_with_bom_removal(bytes).0.into_owned());
+    }
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        return Ok(encoding
+            .decode_without_bom_handling(&bytes[bom_length..])
+            .0
+            .into_owned());
+    }
+    if let Ok(content) = std::str::from_utf8(bytes) {
+        return Ok(content.to_string());
+    }
+    for encoding in legacy_encodings(language) {
+        if let Some(content) = encoding.decode_without_bom_handling_and_without_replacement(bytes) {
+            return Ok(content.into_owned());
+        }
+    }
+    // Any byte is valid, so at least the ASCII text is kept
+    Ok(WINDOWS_1252
+        .decode_without_bom_handling(bytes)
+        .0
+        .into_owned())
+}
+
+/// Remove the `<i>`, `<font ...>` or WebVTT `<v Name>` tags and the `{\an8}` override blocks
+fn strip_tags(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut closing = None;
+    for c in text.chars() {
+        match (closing, c) {
+            (None, '<') => closing = Some('>'),
+            (None, '{') => closing = Some('}'),
+            (None, _) => plain.push(c),
+            (Some(end), _) if c == end => closing = None,
+            _ => {}
+        }
+    }
+    plain
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .replace("&lrm;", "")
+        .replace("&rlm;", "")
+        .replace("&amp;", "&")
+}
+
+/// Parse the `start --> end [settings]` line of a SRT or WebVTT cue
+fn parse_timing(line: &str) -> Option<(f32, f32)> {
+    let (start, end) = line.split_once("-->")?;
+    let end = end.split_whitespace().next()?;
+    Some((parse_timestamp(start).ok()?, parse_timestamp(end).ok()?))
+}
+
+/// Cues of SRT and WebVTT files, a timing line followed by the text until a blank line
+///
+/// Indices and identifiers are ignored, so missing, duplicated or non-numeric ones are fine.
+fn parse_cues(content: &str, decode: fn(&str) -> String) -> Vec<Subtitle> {
+    let lines: Vec<&str> = content.lines().map(|line| line.trim_end()).collect();
+    let timings: Vec<usize> = (0..lines.len())
+        .filter(|&i| lines[i].contains("-->"))
+        .collect();
+
+    let mut subtitles = Vec::new();
+    for (n, &i) in timings.iter().enumerate() {
+        let Some((start, end)) = parse_timing(lines[i]) else {
+            continue;
+        };
+        let next = timings.get(n + 1).copied();
+        let mut text_lines: Vec<&str> = lines[i + 1..next.unwrap_or(lines.len())]
+            .iter()
+            .copied()
+            .take_while(|line| !line.trim().is_empty())
+            .collect();
+        // The index of the next cue without a blank line before it
+        if next == Some(i + 1 + text_lines.len())
+            && text_lines
+                .last()
+                .is_some_and(|line| line.trim().parse::<u32>().is_ok())
+        {
+            text_lines.pop();
+        }
+        let text = text_lines
+            .iter()
+            .map(|line| decode(&strip_tags(line)).trim().to_string())
+            .collect::<Vec<String>>()
+            .join("\n");
+        if !text.is_empty() {
+            subtitles.push(Subtitle::new(start, end, text));
+        }
+    }
+    subtitles
+}
+
+pub fn parse_srt(content: &str) -> Vec<Subtitle> {
+    parse_cues(content, |text| text.to_string())
+}
+
+pub fn parse_vtt(content: &str) -> Vec<Subtitle> {
+    parse_cues(content, decode_entities)
+}
+
+/// The `Dialogue:` events of an ASS or SSA script, in the field order of its `Format:` line
+pub fn parse_ass(content: &str) -> Vec<Subtitle> {
+    let mut format: Vec<String> = "Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, \
+        Effect, Text"
+        .split(',')
+        .map(|field| field.trim().to_lowercase())
+        .collect();
+    let mut in_events = false;
+
+    let mut subtitles = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_events = line.eq_ignore_ascii_case("[Events]");
+            continue;
+        }
+        if !in_events {
+            continue;
+        }
+        if let Some(fields) = line.strip_prefix("Format:") {
+            format = fields
+                .split(',')
+                .map(|field| field.trim().to_lowercase())
+                .collect();
+        } else if let Some(event) = line.strip_prefix("Dialogue:") {
+            // The text is the last field and might contain commas
+            let values: Vec<&str> = event.trim_start().splitn(format.len(), ',').collect();
+            let field = |name: &str| {
+                format
+                    .iter()
+                    .position(|field| field == name)
+                    .and_then(|i| values.get(i))
+            };
+            let (Some(start), Some(end), Some(text)) =
+                (field("start"), field("end"), field("text"))
+            else {
+                continue;
+            };
+            let (Ok(start), Ok(end)) = (parse_timestamp(start), parse_timestamp(end)) else {
+                continue;
+            };
+            let text = ass_text_to_plain(text);
+            if !text.is_empty() {
+                // Rendered with the style of the same name given to the ASS exporter
+                let style = field("style")
+                    .map(|style| style.trim().to_string())
+                    .filter(|style| !style.is_empty());
+                subtitles.push(Subtitle {
+                    style,
+                    ..Subtitle::new(start, end, text)
+                });
+            }
+        }
+    }
+    // Events are not necessarily in order, e.g. signs and dialogues
+    subtitles.sort_by(|a, b| a.start.total_cmp(&b.start));
+    subtitles
+}
+
+/// Read a SRT, WebVTT or ASS file, see `decode_subtitle_bytes` for the encoding
+pub fn read_subtitle_file(
+    path: &str,
+    encoding: Option<&str>,
+    language: &str,
+) -> Result<Vec<Subtitle>, String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    let content = decode_subtitle_bytes(&bytes, encoding, language)?
+        .replace("\r\n", "\n")
+        .replace('\r', "\n");
+    let subtitles = match SubtitleFileFormat::detect(path, &content) {
+        SubtitleFileFormat::Srt => parse_srt(&content),
+        SubtitleFileFormat::Vtt => parse_vtt(&content),
+        SubtitleFileFormat::Ass => parse_ass(&content),
+    };
+    Ok(subtitles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_srt() {
+        // Missing index, missing blank line before an index, extra blank lines and tags
+        let content = "1\n\
+            00:00:01,000 --> 00:00:02,500\n\
+            <i>Hello,</i>\n\
+            world!\n\
+            3\n\
+            00:00:03,000 --> 00:00:04,000 X1:100 X2:200 Y1:10 Y2:20\n\
+            {\\an8}Sign\n\
+            \n\
+            \n\
+            00:01:05,000 --> 00:01:06,000\n\
+            Goodbye\n";
+        let subtitles = parse_srt(content);
+        let cues: Vec<(f32, f32, &str)> = subtitles
+            .iter()
+            .map(|s| (s.start, s.end, s.text.as_str()))
+            .collect();
+        assert_eq!(
+            cues,
+            vec![
+                (1.0, 2.5, "Hello,\nworld!"),
+                (3.0, 4.0, "Sign"),
+                (65.0, 66.0, "Goodbye"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_vtt() {
+        let content = "WEBVTT - Some title\n\
+            \n\
+            STYLE\n\
+            ::cue { color: yellow; }\n\
+            \n\
+            intro\n\
+            00:01.000 --> 00:02.000 line:85% align:center\n\
+            <v Roger>Fish &amp; chips</v>\n\
+            \n\
+            NOTE a comment\n\
+            \n\
+            01:00:03.000 --> 01:00:04.000\n\
+            <c.yellow>1 &lt; 2</c>\n";
+        let subtitles = parse_vtt(content);
+        assert_eq!(subtitles.len(), 2);
+        assert_eq!(
+            (
+                subtitles[0].start,
+                subtitles[0].end,
+                subtitles[0].text.as_str()
+            ),
+            (1.0, 2.0, "Fish & chips")
+        );
+        assert_eq!(
+            (subtitles[1].start, subtitles[1].text.as_str()),
+            (3603.0, "1 < 2")
+        );
+    }
+
+    #[test]
+    fn test_parse_ass() {
+        let content = "[Script Info]\n\
+            ScriptType: v4.00+\n\
+            \n\
+            [Events]\n\
+            Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n\
+            Comment: 0,0:00:00.00,0:00:01.00,Default,,0,0,0,,Not shown\n\
+            Dialogue: 0,0:00:05.00,0:00:06.50,Default,,0,0,0,,{\\i1}Well,{\\i0} yes.\\Nmaybe\n\
+            Dialogue: 0,0:00:01.00,0:00:02.00,Sign,,0,0,0,,{\\pos(10,10)}Station\n";
+        let subtitles = parse_ass(content);
+        let cues: Vec<(f32, f32, &str)> = subtitles
+            .iter()
+            .map(|s| (s.start, s.end, s.text.as_str()))
+            .collect();
+        assert_eq!(
+            cues,
+            vec![(1.0, 2.0, "Station"), (5.0, 6.5, "Well, yes.\nmaybe")]
+        );
+        assert_eq!(subtitles[0].style.as_deref(), Some("Sign"));
+        assert_eq!(subtitles[1].style.as_deref(), Some("Default"));
+    }
+
+    #[test]
+    fn test_decode_subtitle_bytes() {
+        let (bytes, _, _) = SHIFT_JIS.encode("こんにちは");
+        assert_eq!(
+            decode_subtitle_bytes(&bytes, None, "ja").unwrap(),
+            "こんにちは"
+        );
+        let (bytes, _, _) = GBK.encode("你好世界");
+        assert_eq!(
+            decode_subtitle_bytes(&bytes, None, "zh").unwrap(),
+            "你好世界"
+        );
+        assert_eq!(
+            decode_subtitle_bytes(&bytes, Some("gbk"), "ja").unwrap(),
+            "你好世界"
+        );
+        assert_eq!(
+            decode_subtitle_bytes("\u{feff}Hello".as_bytes(), None, "en").unwrap(),
+            "Hello"
+        );
+        assert_eq!(
+            decode_subtitle_bytes(b"\xff\xfeH\x00i\x00", None, "en").unwrap(),
+            "Hi"
+        );
+        assert!(decode_subtitle_bytes(b"Hello", Some("klingon"), "en").is_err());
+    }
+
+    #[test]
+    fn test_read_subtitle_file() {
+        use tempfile::TempDir;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("input.srt");
+        let content = "1\r\n00:00:01,000 --> 00:00:02,000\r\nありがとう\r\n\r\n";
+        let (bytes, _, _) = SHIFT_JIS.encode(content);
+        std::fs::write(&path, bytes).unwrap();
+
+        let subtitles = read_subtitle_file(path.to_str().unwrap(), None, "ja").unwrap();
+        assert_eq!(subtitles.len(), 1);
+        assert_eq!(subtitles[0].text, "ありがとう");
+    }
+}
@@ -0,0 +1,125 @@
+use super::Subtitle;
+
+/// Parse a timestamp given in seconds ("90", "90.5") or as "[HH:]MM:SS[.mmm]"
+pub fn parse_timestamp(timestamp: &str) -> Result<f32, String> {
+    let timestamp = timestamp.trim();
+    let invalid = || format!("Invalid timestamp {}", timestamp);
+
+    let mut seconds = 0.0;
+    let parts: Vec<&str> = timestamp.split(':').collect();
+    if parts.len() > 3 {
+        return Err(invalid());
+    }
+    for (i, part) in parts.iter().enumerate() {
+        let is_last = i == parts.len() - 1;
+        let value = if is_last {
+            // Accept both "SS.mmm" and the SRT style "SS,mmm"
+            part.replace(',', ".")
+                .parse::<f32>()
+                .map_err(|_| invalid())?
+        } else {
+            part.parse::<u32>().map_err(|_| invalid())? as f32
+        };
+        if value < 0.0 || (parts.len() > 1 && i > 0 && value >= 60.0) {
+            return Err(invalid());
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Ok(seconds)
+}
+
+/// Part of the video to work on, `end` is `None` until the end of the video
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TimeRange {
+    pub start: f32,
+    pub end: Option<f32>,
+}
+
+impl TimeRange {
+    pub fn new(start: f32, end: Option<f32>) -> TimeRange {
+        TimeRange { start, end }
+    }
+
+    /// Build a range from command line values, where an end of "0" means the end of the video
+    pub fn parse(start: &str, end: &str) -> Result<TimeRange, String> {
+        let start = parse_timestamp(start)?;
+        let end = parse_timestamp(end)?;
+        if end == 0.0 {
+            return Ok(TimeRange::new(start, None));
+        }
+        if end <= start {
+            return Err(format!("End time {} is before start time {}", end, start));
+        }
+        Ok(TimeRange::new(start, Some(end)))
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.start <= 0.0 && self.end.is_none()
+    }
+
+    /// Move subtitles timed relatively to the range start back to the original timeline
+    pub fn offset_subtitles(&self, subtitles: &mut [Subtitle]) {
+        for subtitle in subtitles.iter_mut() {
+            subtitle.shift(self.start);
+        }
+    }
+
+    /// Keep only the subtitles overlapping the range, clamped to it
+    pub fn clip_subtitles(&self, subtitles: Vec<Subtitle>) -> Vec<Subtitle> {
+        let end = self.end.unwrap_or(f32::MAX);
+        subtitles
+            .into_iter()
+            .filter(|s| s.end > self.start && s.start < end)
+            .map(|mut s| {
+                s.start = s.start.max(self.start);
+                s.end = s.end.min(end);
+                s
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("0"), Ok(0.0));
+        assert_eq!(parse_timestamp("90.5"), Ok(90.5));
+        assert_eq!(parse_timestamp("01:30"), Ok(90.0));
+        assert_eq!(parse_timestamp("01:02:03.500"), Ok(3723.5));
+        assert_eq!(parse_timestamp("00:00:01,250"), Ok(1.25));
+        assert!(parse_timestamp("00:61:00").is_err());
+        assert!(parse_timestamp("1:2:3:4").is_err());
+        assert!(parse_timestamp("abc").is_err());
+    }
+
+    #[test]
+    fn test_time_range() {
+        assert!(TimeRange::parse("0", "0").unwrap().is_full());
+        assert_eq!(
+            TimeRange::parse("00:01:00", "00:02:00.5"),
+            Ok(TimeRange::new(60.0, Some(120.5)))
+        );
+        assert!(TimeRange::parse("10", "5").is_err());
+
+        let range = TimeRange::new(10.0, Some(20.0));
+        let mut subtitles = vec![
+            Subtitle::new(0.0, 1.0, "a".to_string()),
+            Subtitle::new(8.0, 12.0, "b".to_string()),
+        ];
+        range.offset_subtitles(&mut subtitles);
+        assert_eq!((subtitles[0].start, subtitles[0].end), (10.0, 11.0));
+
+        let clipped = range.clip_subtitles(vec![
+            Subtitle::new(5.0, 9.0, "before".to_string()),
+            Subtitle::new(8.0, 12.0, "overlap".to_string()),
+            Subtitle::new(19.0, 25.0, "after".to_string()),
+            Subtitle::new(21.0, 25.0, "outside".to_string()),
+        ]);
+        assert_eq!(clipped.len(), 2);
+        assert_eq!((clipped[0].start, clipped[0].end), (10.0, 12.0));
+        assert_eq!((clipped[1].start, clipped[1].end), (19.0, 20.0));
+    }
+}
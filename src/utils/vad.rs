@@ -0,0 +1,180 @@
+/// A region of the audio containing speech, in seconds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpeechRegion {
+    pub start: f32,
+    pub end: f32,
+}
+
+/// Classify short frames of audio as speech or not, e.g. energy based or a model
+pub trait VoiceDetector {
+    fn is_speech(&mut self, frame: &[f32]) -> bool;
+}
+
+/// Speech is loud enough and does not cross zero as often as noise or hiss
+pub struct EnergyVoiceDetector {
+    /// Minimum RMS level of a speech frame, in dBFS
+    pub energy_threshold: f32,
+    /// Maximum ratio of sign changes between consecutive samples of a speech frame
+    pub max_zero_crossing_rate: f32,
+}
+
+impl Default for EnergyVoiceDetector {
+    fn default() -> Self {
+        EnergyVoiceDetector {
+            energy_threshold: -40.0,
+            max_zero_crossing_rate: 0.35,
+        }
+    }
+}
+
+pub(crate) fn rms_db(frame: &[f32]) -> f32 {
+    let mean_square = frame.iter().map(|s| s * s).sum::<f32>() / frame.len().max(1) as f32;
+    10.0 * mean_square.max(1e-10).log10()
+}
+
+fn zero_crossing_rate(frame: &[f32]) -> f32 {
+    let crossings = frame
+        .windows(2)
+        .filter(|pair| (pair[0] >= 0.0) != (pair[1] >= 0.0))
+        .count();
+    crossings as f32 / frame.len().max(1) as f32
+}
+
+impl VoiceDetector for EnergyVoiceDetector {
+    fn is_speech(&mut self, frame: &[f32]) -> bool {
+        rms_db(frame) >= self.energy_threshold
+            && zero_crossing_rate(frame) <= self.max_zero_crossing_rate
+    }
+}
+
+pub struct VadOptions {
+    /// Duration of the frames given to the detector
+    pub frame_duration: f32,
+    /// Shorter speech is dropped (clicks, short noises)
+    pub min_speech_duration: f32,
+    /// Shorter pauses do not split a region
+    pub min_silence_duration: f32,
+    /// Added before and after each region so word edges are not cut
+    pub padding: f32,
+}
+
+impl Default for VadOptions {
+    fn default() -> Self {
+        VadOptions {
+            frame_duration: 0.03,
+            min_speech_duration: 0.25,
+            min_silence_duration: 0.5,
+            padding: 0.2,
+        }
+    }
+}
+
+/// Find the regions of the samples containing speech
+pub fn detect_speech_regions(
+    samples: &[f32],
+    sample_rate: u32,
+    detector: &mut dyn VoiceDetector,
+    options: &VadOptions,
+) -> Vec<SpeechRegion> {
+    let frame_len = ((options.frame_duration * sample_rate as f32) as usize).max(1);
+    let frame_duration = frame_len as f32 / sample_rate as f32;
+    let total_duration = samples.len() as f32 / sample_rate as f32;
+
+    // Merge the speech frames, pauses shorter than the minimum silence included
+    let mut regions: Vec<SpeechRegion> = Vec::new();
+    let mut current: Option<SpeechRegion> = None;
+    for (i, frame) in samples.chunks(frame_len).enumerate() {
+        if !detector.is_speech(frame) {
+            continue;
+        }
+        let start = i as f32 * frame_duration;
+        let end = (start + frame_duration).min(total_duration);
+        current = match current {
+            Some(region) if start - region.end < options.min_silence_duration => {
+                Some(SpeechRegion { end, ..region })
+            }
+            Some(region) => {
+                regions.push(region);
+                Some(SpeechRegion { start, end })
+            }
+            None => Some(SpeechRegion { start, end }),
+        };
+    }
+    regions.extend(current);
+
+    // Pad the remaining regions, merging the ones overlapping after padding
+    let mut padded: Vec<SpeechRegion> = Vec::new();
+    for region in regions
+        .into_iter()
+        .filter(|region| region.end - region.start >= options.min_speech_duration)
+    {
+        let start = (region.start - options.padding).max(0.0);
+        let end = (region.end + options.padding).min(total_duration);
+        match padded.last_mut() {
+            Some(last) if start <= last.end => last.end = end,
+            _ => padded.push(SpeechRegion { start, end }),
+        }
+    }
+    padded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Silence, `tone` seconds of a 220 Hz tone, silence
+    fn tone_between_silences(silence: f32, tone: f32) -> Vec<f32> {
+        let silence = vec![0.0; (silence * 16000.0) as usize];
+        let tone = (0..(tone * 16000.0) as usize)
+            .map(|i| (2.0 * std::f32::consts::PI * 220.0 * i as f32 / 16000.0).sin() * 0.5);
+        silence
+            .iter()
+            .copied()
+            .chain(tone)
+            .chain(silence.iter().copied())
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_speech_regions() {
+        let samples = tone_between_silences(1.0, 2.0);
+        let regions = detect_speech_regions(
+            &samples,
+            16000,
+            &mut EnergyVoiceDetector::default(),
+            &VadOptions::default(),
+        );
+        assert_eq!(regions.len(), 1);
+        assert!((regions[0].start - 0.8).abs() < 0.05);
+        assert!((regions[0].end - 3.2).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_detect_speech_regions_drops_short_noises() {
+        let mut samples = tone_between_silences(1.0, 0.1);
+        samples.extend(tone_between_silences(1.0, 1.0));
+        let regions = detect_speech_regions(
+            &samples,
+            16000,
+            &mut EnergyVoiceDetector::default(),
+            &VadOptions::default(),
+        );
+        assert_eq!(regions.len(), 1);
+        assert!(regions[0].start > 2.5);
+    }
+
+    #[test]
+    fn test_detect_speech_regions_ignores_hiss() {
+        // Alternating samples cross zero every sample
+        let samples: Vec<f32> = (0..32000)
+            .map(|i| if i % 2 == 0 { 0.3 } else { -0.3 })
+            .collect();
+        let regions = detect_speech_regions(
+            &samples,
+            16000,
+            &mut EnergyVoiceDetector::default(),
+            &VadOptions::default(),
+        );
+        assert!(regions.is_empty());
+    }
+}
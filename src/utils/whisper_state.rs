@@ -1,6 +1,77 @@
-use whisper_rs::WhisperState;
+use whisper_rs::{WhisperState, WhisperTokenData};
 
-use super::Subtitle;
+use super::{is_cjk, Subtitle, Word};
+
+fn create_word(text: &str, tokens: &[WhisperTokenData]) -> Word {
+    Word {
+        start: tokens[0].t0 as f32 / 100.,
+        end: tokens[tokens.len() - 1].t1 as f32 / 100.,
+        text: text.to_string(),
+        probability: tokens.iter().map(|token| token.p).fold(1.0, f32::min),
+    }
+}
+
+/// Timed text of the tokens of a segment, special tokens excluded
+fn create_tokens_from_whisper_state(state: &WhisperState, segment: i32, text: &str) -> Vec<Word> {
+    let num_tokens = state
+        .full_n_tokens(segment)
+        .expect("failed to get number of tokens");
+
+    let mut tokens = Vec::new();
+    // Tokens holding a part of a character, their text is taken from the segment text
+    let mut partial: Vec<WhisperTokenData> = Vec::new();
+    let mut position = 0;
+    for i in 0..num_tokens {
+        let data = state
+            .full_get_token_data(segment, i)
+            .expect("failed to get token data");
+        let Ok(token) = state.full_get_token_text(segment, i) else {
+            partial.push(data);
+            continue;
+        };
+        let rest = &text[position..];
+        let skipped = if partial.is_empty() {
+            rest.starts_with(&token).then_some(0)
+        } else {
+            rest.find(&token)
+        };
+        // Special tokens (e.g. "[_BEG_]") are not part of the segment text
+        let Some(skipped) = skipped else {
+            continue;
+        };
+        if !partial.is_empty() {
+            tokens.push(create_word(&rest[..skipped], &partial));
+            partial.clear();
+        }
+        tokens.push(create_word(&token, &[data]));
+        position += skipped + token.len();
+    }
+    if !partial.is_empty() {
+        tokens.push(create_word(&text[position..], &partial));
+    }
+    tokens
+}
+
+/// Join the tokens into words, on spaces or at each CJK character
+fn join_tokens_into_words(tokens: Vec<Word>) -> Vec<Word> {
+    let mut words: Vec<Word> = Vec::new();
+    for token in tokens {
+        let starts_word = token
+            .text
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_whitespace() || is_cjk(c));
+        match words.last_mut() {
+            Some(word) if !starts_word => {
+                word.end = token.end;
+                word.text.push_str(&token.text);
+                word.probability = word.probability.min(token.probability);
+            }
+            _ => words.push(token),
+        }
+    }
+    words
+}
 
 pub fn create_subtitle_from_whisper_state(state: &WhisperState) -> Vec<Subtitle> {
     let num_segments = state
@@ -19,11 +90,15 @@ pub fn create_subtitle_from_whisper_state(state: &WhisperState) -> Vec<Subtitle>
             .full_get_segment_t1(i)
             .expect("failed to get segment end timestamp");
 
-        subtitles.push(Subtitle::new(
-            start_timestamp as f32 / 100.,
-            end_timestamp as f32 / 100.,
-            segment,
-        ));
+        let tokens = create_tokens_from_whisper_state(state, i, &segment);
+        subtitles.push(Subtitle {
+            words: join_tokens_into_words(tokens),
+            ..Subtitle::new(
+                start_timestamp as f32 / 100.,
+                end_timestamp as f32 / 100.,
+                segment,
+            )
+        });
     }
 
     subtitles
@@ -123,5 +198,37 @@ mod tests {
 
         let subtitles = create_subtitle_from_whisper_state(&state);
         assert_ne!(subtitles.len(), 0);
+        for subtitle in subtitles.iter() {
+            let text: String = subtitle.words.iter().map(|w| w.text.as_str()).collect();
+            assert_eq!(text, subtitle.text);
+            assert!(subtitle
+                .words
+                .iter()
+                .all(|w| (0.0..=1.0).contains(&w.probability)));
+        }
+    }
+
+    #[test]
+    fn test_join_tokens_into_words() {
+        let token = |start: f32, text: &str, probability: f32| Word {
+            start,
+            end: start + 0.1,
+            text: text.to_string(),
+            probability,
+        };
+        let words = join_tokens_into_words(vec![
+            token(0.0, " And", 0.9),
+            token(0.1, " so", 0.8),
+            token(0.2, " my", 0.9),
+            token(0.3, " fell", 0.7),
+            token(0.4, "ow", 0.5),
+            token(0.5, ",", 0.9),
+            token(0.6, "日本", 0.9),
+            token(0.7, "語", 0.6),
+        ]);
+        let texts: Vec<&str> = words.iter().map(|w| w.text.as_str()).collect();
+        assert_eq!(texts, vec![" And", " so", " my", " fellow,", "日本", "語"]);
+        assert_eq!((words[3].start, words[3].end), (0.3, 0.6));
+        assert_eq!(words[3].probability, 0.5);
     }
 }
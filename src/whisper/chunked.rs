@@ -0,0 +1,360 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use whisper_rs::{WhisperContext, WhisperContextParameters};
+
+use super::experiment::{full_params, WhisperOptions};
+use crate::utils::vad::{rms_db, SpeechRegion};
+use crate::utils::whisper_state::create_subtitle_from_whisper_state;
+use crate::utils::Subtitle;
+
+const SAMPLE_RATE: u32 = 16000;
+
+pub struct ChunkOptions {
+    /// Target duration of a chunk, in seconds
+    pub chunk_duration: f32,
+    /// Audio shared by consecutive chunks, so words at the cut are heard by one of them
+    pub overlap: f32,
+    /// How far before the target end of a chunk to look for the quietest point to cut at
+    pub search_window: f32,
+    /// Number of whisper states running in parallel
+    pub jobs: usize,
+}
+
+impl Default for ChunkOptions {
+    fn default() -> Self {
+        ChunkOptions {
+            chunk_duration: 300.0,
+            overlap: 2.0,
+            search_window: 10.0,
+            jobs: 2,
+        }
+    }
+}
+
+/// A chunk of the samples, owning the subtitles starting between the cuts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioChunk {
+    /// Samples given to whisper, cuts with the overlap
+    pub start: usize,
+    pub end: usize,
+    /// Cuts with the previous and next chunks
+    pub cut_start: usize,
+    pub cut_end: usize,
+}
+
+/// Find the quietest 100ms frame of the samples, returning the index of its center
+fn quietest_point(samples: &[f32], sample_rate: u32) -> usize {
+    let frame_len = (sample_rate / 10) as usize;
+    samples
+        .chunks(frame_len)
+        .enumerate()
+        .min_by(|(_, a), (_, b)| rms_db(a).total_cmp(&rms_db(b)))
+        .map(|(i, frame)| i * frame_len + frame.len() / 2)
+        .unwrap_or(0)
+}
+
+/// Split the samples into chunks cut at silences, with some overlap between chunks
+pub fn split_into_chunks(
+    samples: &[f32],
+    sample_rate: u32,
+    options: &ChunkOptions,
+) -> Vec<AudioChunk> {
+    let chunk_len = ((options.chunk_duration * sample_rate as f32) as usize).max(1);
+    let overlap = (options.overlap * sample_rate as f32) as usize;
+    let search_len = ((options.search_window * sample_rate as f32) as usize).min(chunk_len / 2);
+
+    let mut cuts = vec![0];
+    let mut position = 0;
+    while samples.len() - position > chunk_len {
+        let search_start = position + chunk_len - search_len;
+        let search_end = position + chunk_len;
+        position = search_start + quietest_point(&samples[search_start..search_end], sample_rate);
+        cuts.push(position);
+    }
+    cuts.push(samples.len());
+
+    cuts.windows(2)
+        .map(|cut| AudioChunk {
+            start: cut[0].saturating_sub(overlap),
+            end: (cut[1] + overlap).min(samples.len()),
+            cut_start: cut[0],
+            cut_end: cut[1],
+        })
+        .collect()
+}
+
+/// Merge the subtitles of the chunks, dropping the ones transcribed twice in the overlaps
+fn stitch_chunks(
+    chunks: &[AudioChunk],
+    chunk_subtitles: Vec<Vec<Subtitle>>,
+    sample_rate: u32,
+) -> Vec<Subtitle> {
+    let mut subtitles: Vec<Subtitle> = Vec::new();
+    for (chunk, chunk_subtitles) in chunks.iter().zip(chunk_subtitles) {
+        let cut_start = chunk.cut_start as f32 / sample_rate as f32;
+        let cut_end = chunk.cut_end as f32 / sample_rate as f32;
+        // Each chunk keeps the subtitles centered between its cuts
+        subtitles.extend(chunk_subtitles.into_iter().filter(|subtitle| {
+            let center = (subtitle.start + subtitle.end) / 2.0;
+            center >= cut_start && (center < cut_end || chunk.cut_end == chunk.end)
+        }));
+    }
+    subtitles.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+    // The same words heard by both chunks around a cut
+    let mut stitched: Vec<Subtitle> = Vec::with_capacity(subtitles.len());
+    for subtitle in subtitles {
+        if let Some(last) = stitched.last() {
+            if subtitle.start < last.end && subtitle.text.trim() == last.text.trim() {
+                continue;
+            }
+        }
+        stitched.push(subtitle);
+    }
+    stitched
+}
+
+/// Run whisper on ranges of the samples with `jobs` states in parallel, sharing one model
+///
+/// Returns the subtitles of each range in order, timed relative to the start of the samples.
+fn transcribe_ranges_in_parallel(
+    model_path: &str,
+    samples: &[f32],
+    ranges: &[Range<usize>],
+    language: &str,
+    translate: bool,
+    whisper_options: &WhisperOptions,
+    jobs: usize,
+) -> Vec<Vec<Subtitle>> {
+    let jobs = jobs.clamp(1, ranges.len().max(1));
+    // Share the cores between the states, unless the threads of a state are given
+    let threads_per_job = whisper_options.n_threads.unwrap_or_else(|| {
+        let threads = std::thread::available_parallelism().map_or(4, |n| n.get());
+        (threads / jobs).max(1)
+    });
+    let whisper_options = WhisperOptions {
+        n_threads: Some(threads_per_job),
+        ..whisper_options.clone()
+    };
+    println!(
+        "Transcribing {} parts with {} jobs of {} threads",
+        ranges.len(),
+        jobs,
+        threads_per_job
+    );
+
+    let ctx = WhisperContext::new_with_params(model_path, WhisperContextParameters::default())
+        .expect("failed to load model");
+    let next_range = AtomicUsize::new(0);
+    let mut results: Vec<(usize, Vec<Subtitle>)> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut state = ctx.create_state().expect("failed to create state");
+                    let mut results = Vec::new();
+                    loop {
+                        let i = next_range.fetch_add(1, Ordering::SeqCst);
+                        let Some(range) = ranges.get(i) else {
+                            break;
+                        };
+                        let params = full_params(language, translate, &whisper_options);
+                        state
+                            .full(params, &samples[range.clone()])
+                            .expect("failed to run model");
+
+                        let offset = range.start as f32 / SAMPLE_RATE as f32;
+                        let mut subtitles = create_subtitle_from_whisper_state(&state);
+                        for subtitle in subtitles.iter_mut() {
+                            subtitle.shift(offset);
+                        }
+                        results.push((i, subtitles));
+                    }
+                    results
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    results
+        .into_iter()
+        .map(|(_, subtitles)| subtitles)
+        .collect()
+}
+
+/// Run whisper on chunks of 16 kHz mono f32 samples in parallel, sharing one model
+///
+/// The subtitles are timed relative to the start of the samples.
+pub fn extract_and_translate_chunked_from_f32_16khz_samples(
+    model_path: &str,
+    samples: &[f32],
+    language: &str,
+    translate: bool,
+    whisper_options: &WhisperOptions,
+    options: &ChunkOptions,
+) -> Vec<Subtitle> {
+    let chunks = split_into_chunks(samples, SAMPLE_RATE, options);
+    let ranges: Vec<Range<usize>> = chunks.iter().map(|chunk| chunk.start..chunk.end).collect();
+    let chunk_subtitles = transcribe_ranges_in_parallel(
+        model_path,
+        samples,
+        &ranges,
+        language,
+        translate,
+        whisper_options,
+        options.jobs,
+    );
+    stitch_chunks(&chunks, chunk_subtitles, SAMPLE_RATE)
+}
+
+/// Sample ranges of the speech regions, the empty ones are dropped
+fn speech_region_ranges(regions: &[SpeechRegion], len: usize) -> Vec<Range<usize>> {
+    regions
+        .iter()
+        .map(|region| {
+            let start = ((region.start * SAMPLE_RATE as f32) as usize).min(len);
+            let end = ((region.end * SAMPLE_RATE as f32) as usize).min(len);
+            start..end
+        })
+        .filter(|range| !range.is_empty())
+        .collect()
+}
+
+/// Run whisper on the speech regions of 16 kHz mono f32 samples, `jobs` regions in parallel
+///
+/// The subtitles are timed relative to the start of the samples.
+pub fn extract_and_translate_regions_from_f32_16khz_samples(
+    model_path: &str,
+    samples: &[f32],
+    regions: &[SpeechRegion],
+    language: &str,
+    translate: bool,
+    whisper_options: &WhisperOptions,
+    jobs: usize,
+) -> Vec<Subtitle> {
+    let ranges = speech_region_ranges(regions, samples.len());
+    transcribe_ranges_in_parallel(
+        model_path,
+        samples,
+        &ranges,
+        language,
+        translate,
+        whisper_options,
+        jobs,
+    )
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_into_chunks() {
+        // 10s of loud signal with 0.5s silences at 2.5s, 4.5s and 7.5s
+        let mut samples: Vec<f32> = (0..16000 * 10)
+            .map(|i| if i % 2 == 0 { 0.5 } else { -0.5 })
+            .collect();
+        for silence in [2.5, 4.5, 7.5] {
+            let start = (silence * 16000.0) as usize;
+            samples[start..start + 8000].fill(0.0);
+        }
+        let options = ChunkOptions {
+            chunk_duration: 3.0,
+            overlap: 0.5,
+            search_window: 1.0,
+            jobs: 2,
+        };
+        let chunks = split_into_chunks(&samples, 16000, &options);
+
+        let cuts: Vec<usize> = chunks.iter().map(|chunk| chunk.cut_end).collect();
+        assert_eq!(cuts, vec![40800, 73600, 120800, 160000]);
+        assert_eq!(chunks[0].start, 0);
+        assert_eq!(chunks[1].start, 40800 - 8000);
+        assert_eq!(chunks[1].end, 73600 + 8000);
+        assert_eq!(chunks[3].end, 160000);
+    }
+
+    #[test]
+    fn test_split_short_samples() {
+        let samples = vec![0.0; 16000];
+        let chunks = split_into_chunks(&samples, 16000, &ChunkOptions::default());
+        assert_eq!(
+            chunks,
+            vec![AudioChunk {
+                start: 0,
+                end: 16000,
+                cut_start: 0,
+                cut_end: 16000,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_speech_region_ranges() {
+        let regions = vec![
+            SpeechRegion {
+                start: 0.5,
+                end: 1.0,
+            },
+            // Past the end of the samples
+            SpeechRegion {
+                start: 3.0,
+                end: 4.0,
+            },
+            SpeechRegion {
+                start: 1.5,
+                end: 2.5,
+            },
+        ];
+        assert_eq!(
+            speech_region_ranges(&regions, 16000 * 2),
+            vec![8000..16000, 24000..32000]
+        );
+    }
+
+    #[test]
+    fn test_stitch_chunks() {
+        let chunks = vec![
+            AudioChunk {
+                start: 0,
+                end: 16000 * 6,
+                cut_start: 0,
+                cut_end: 16000 * 5,
+            },
+            AudioChunk {
+                start: 16000 * 4,
+                end: 16000 * 10,
+                cut_start: 16000 * 5,
+                cut_end: 16000 * 10,
+            },
+        ];
+        let subtitles = stitch_chunks(
+            &chunks,
+            vec![
+                vec![
+                    Subtitle::new(0.0, 2.0, "one".to_string()),
+                    Subtitle::new(4.0, 5.5, "two".to_string()),
+                    // Cut in the middle by the end of the chunk
+                    Subtitle::new(5.5, 6.0, "thr".to_string()),
+                ],
+                vec![
+                    // Cut in the middle by the start of the chunk
+                    Subtitle::new(4.0, 4.4, "wo".to_string()),
+                    Subtitle::new(4.9, 5.2, "two".to_string()),
+                    Subtitle::new(5.5, 7.0, "three".to_string()),
+                    Subtitle::new(8.0, 10.0, "four".to_string()),
+                ],
+            ],
+            16000,
+        );
+        let texts: Vec<&str> = subtitles.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, vec!["one", "two", "three", "four"]);
+    }
+}
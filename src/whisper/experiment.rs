@@ -2,38 +2,78 @@
 wget https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-tiny.bin
 */
 
-use hound;
+use crate::utils::vad::SpeechRegion;
+use crate::utils::whisper_state::create_subtitle_from_whisper_state;
+use crate::utils::Subtitle;
 use whisper_rs::{
-    FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperState,
+    FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperError,
+    WhisperState,
 };
 
-pub fn extract_from_f32_16khz_wav_audio(
-    model_path: &str,
-    wav_path: &str,
-    language: &str,
-) -> WhisperState {
-    extract_and_translate_from_f32_16khz_wav_audio(model_path, wav_path, language, false)
+/// Decoding parameters of whisper, the defaults are the ones of whisper.cpp
+#[derive(Clone, Debug)]
+pub struct WhisperOptions {
+    /// Beam search width, greedy decoding if 0 or 1
+    pub beam_size: usize,
+    /// Initial decoding temperature
+    pub temperature: f32,
+    /// Temperature increase when the decoding fails (too repetitive or unlikely), 0 disables the fallback
+    pub temperature_inc: f32,
+    /// CPU threads of a whisper state, whisper.cpp picks if None
+    pub n_threads: Option<usize>,
+    /// Text given as previous context, e.g. to prime names and terminology
+    pub initial_prompt: Option<String>,
+    /// Do not use the text of the previous segments as context
+    pub no_context: bool,
+    pub suppress_blank: bool,
+    pub suppress_non_speech_tokens: bool,
+    /// Maximum segment length in characters, 0 for no limit
+    pub max_len: usize,
+}
+
+impl Default for WhisperOptions {
+    fn default() -> Self {
+        WhisperOptions {
+            beam_size: 0,
+            temperature: 0.0,
+            temperature_inc: 0.2,
+            n_threads: None,
+            initial_prompt: None,
+            no_context: true,
+            suppress_blank: true,
+            suppress_non_speech_tokens: false,
+            max_len: 0,
+        }
+    }
+}
+
+fn sampling_strategy(options: &WhisperOptions) -> SamplingStrategy {
+    if options.beam_size > 1 {
+        SamplingStrategy::BeamSearch {
+            beam_size: options.beam_size as i32,
+            // not implemented in whisper.cpp, -1 is its default
+            patience: -1.0,
+        }
+    } else {
+        SamplingStrategy::Greedy { best_of: 1 }
+    }
 }
 
-pub fn extract_and_translate_from_f32_16khz_wav_audio(
+pub fn extract_from_f32_16khz_samples(
     model_path: &str,
-    wav_path: &str,
+    samples: &[f32],
     language: &str,
-    translate: bool,
+    options: &WhisperOptions,
 ) -> WhisperState {
-    let samples: Vec<f32> = hound::WavReader::open(wav_path)
-        .unwrap()
-        .into_samples::<f32>()
-        .map(|x| x.unwrap())
-        .collect();
-
-    // load a context and model
-    let ctx = WhisperContext::new_with_params(model_path, WhisperContextParameters::default())
-        .expect("failed to load model");
-
-    let mut state = ctx.create_state().expect("failed to create state");
+    extract_and_translate_from_f32_16khz_samples(model_path, samples, language, false, options)
+}
 
-    let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+pub(super) fn full_params<'a>(
+    language: &'a str,
+    translate: bool,
+    options: &WhisperOptions,
+) -> FullParams<'a, 'a> {
+    let mut params = FullParams::new(sampling_strategy(options));
 
     if translate {
         // we can also translate the output to english directly in the model
@@ -49,6 +89,43 @@ pub fn extract_and_translate_from_f32_16khz_wav_audio(
     params.set_print_realtime(false);
     params.set_print_timestamps(false);
 
+    // needed for the timing of the words
+    params.set_token_timestamps(true);
+    params.set_temperature(options.temperature);
+    params.set_temperature_inc(options.temperature_inc);
+    if let Some(n_threads) = options.n_threads {
+        params.set_n_threads(n_threads as i32);
+    }
+    if let Some(initial_prompt) = &options.initial_prompt {
+        params.set_initial_prompt(initial_prompt);
+    }
+    params.set_no_context(options.no_context);
+    params.set_suppress_blank(options.suppress_blank);
+    params.set_suppress_non_speech_tokens(options.suppress_non_speech_tokens);
+    if options.max_len > 0 {
+        params.set_split_on_word(true);
+        params.set_max_len(options.max_len as i32);
+    }
+
+    params
+}
+
+/// Run whisper on 16 kHz mono f32 samples, e.g. decoded by `utils::ffmpeg_audio`
+pub fn extract_and_translate_from_f32_16khz_samples(
+    model_path: &str,
+    samples: &[f32],
+    language: &str,
+    translate: bool,
+    options: &WhisperOptions,
+) -> WhisperState {
+    // load a context and model
+    let ctx = WhisperContext::new_with_params(model_path, WhisperContextParameters::default())
+        .expect("failed to load model");
+
+    let mut state = ctx.create_state().expect("failed to create state");
+
+    let params = full_params(language, translate, options);
+
     // we must convert to 16KHz mono f32 samples for the model
     // if needed (smile by Inoki)
     // some utilities exist for this
@@ -64,13 +141,83 @@ pub fn extract_and_translate_from_f32_16khz_wav_audio(
 
     // now we can run the model
     // note the key we use here is the one we created above
-    state
-        .full(params, &samples[..])
-        .expect("failed to run model");
+    state.full(params, samples).expect("failed to run model");
 
     state
 }
 
+/// Spoken language detected by whisper
+#[derive(Clone, Debug)]
+pub struct DetectedLanguage {
+    /// Language code, e.g. "ja"
+    pub code: String,
+    pub probability: f32,
+}
+
+/// Detect the spoken language on the first `duration` seconds of 16 kHz mono f32 samples
+pub fn detect_language_from_f32_16khz_samples(
+    model_path: &str,
+    samples: &[f32],
+    duration: f32,
+    options: &WhisperOptions,
+) -> Result<DetectedLanguage, WhisperError> {
+    let ctx = WhisperContext::new_with_params(model_path, WhisperContextParameters::default())
+        .expect("failed to load model");
+    let mut state = ctx.create_state().expect("failed to create state");
+    let n_threads = options
+        .n_threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(4, |n| n.get().min(4)));
+
+    let end = ((duration * 16000.0) as usize).min(samples.len());
+    state.pcm_to_mel(&samples[..end], n_threads)?;
+    let (id, probabilities) = state.lang_detect(0, n_threads)?;
+    let code = whisper_rs::get_lang_str(id).ok_or(WhisperError::GenericError(id))?;
+    Ok(DetectedLanguage {
+        code: code.to_string(),
+        probability: probabilities[id as usize],
+    })
+}
+
+/// Run whisper on the speech regions of 16 kHz mono f32 samples only, `jobs` regions in parallel
+///
+/// The subtitles are timed relative to the start of the samples.
+pub fn extract_and_translate_speech_regions_from_f32_16khz_samples(
+    model_path: &str,
+    samples: &[f32],
+    regions: &[SpeechRegion],
+    language: &str,
+    translate: bool,
+    options: &WhisperOptions,
+    jobs: usize,
+) -> Vec<Subtitle> {
+    if jobs > 1 {
+        return super::chunked::extract_and_translate_regions_from_f32_16khz_samples(
+            model_path, samples, regions, language, translate, options, jobs,
+        );
+    }
+    let ctx = WhisperContext::new_with_params(model_path, WhisperContextParameters::default())
+        .expect("failed to load model");
+    let mut state = ctx.create_state().expect("failed to create state");
+    let params = full_params(language, translate, options);
+
+    let mut subtitles = Vec::new();
+    for region in regions {
+        let start = ((region.start * 16000.0) as usize).min(samples.len());
+        let end = ((region.end * 16000.0) as usize).min(samples.len());
+        if start >= end {
+            continue;
+        }
+        state
+            .full(params.clone(), &samples[start..end])
+            .expect("failed to run model");
+        for mut subtitle in create_subtitle_from_whisper_state(&state) {
+            subtitle.shift(region.start);
+            subtitles.push(subtitle);
+        }
+    }
+    subtitles
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -150,10 +297,17 @@ mod tests {
     }
 
     #[test]
-    fn test_extract_from_f32_16khz_wav_audio() {
+    fn test_extract_from_f32_16khz_samples() {
         let (audio_path, model_path) = setup();
 
-        let raw_state = extract_from_f32_16khz_wav_audio(&model_path, &audio_path, "en");
+        let samples: Vec<f32> = hound::WavReader::open(audio_path)
+            .unwrap()
+            .into_samples::<f32>()
+            .map(|x| x.unwrap())
+            .collect();
+
+        let raw_state =
+            extract_from_f32_16khz_samples(&model_path, &samples, "en", &WhisperOptions::default());
         assert!(
             raw_state
                 .full_n_segments()
@@ -161,8 +315,13 @@ mod tests {
                 > 0
         );
 
-        let translated_state =
-            extract_and_translate_from_f32_16khz_wav_audio(&model_path, &audio_path, "de", true);
+        let translated_state = extract_and_translate_from_f32_16khz_samples(
+            &model_path,
+            &samples,
+            "de",
+            true,
+            &WhisperOptions::default(),
+        );
         assert!(
             translated_state
                 .full_n_segments()
@@ -170,4 +329,81 @@ mod tests {
                 > 0
         );
     }
+
+    #[test]
+    fn test_extract_speech_regions_from_f32_16khz_samples() {
+        let (audio_path, model_path) = setup();
+
+        let mut samples: Vec<f32> = vec![0.0; 16000 * 2];
+        samples.extend(
+            hound::WavReader::open(audio_path)
+                .unwrap()
+                .into_samples::<f32>()
+                .map(|x| x.unwrap()),
+        );
+        let regions = vec![SpeechRegion {
+            start: 2.0,
+            end: samples.len() as f32 / 16000.0,
+        }];
+        let subtitles = extract_and_translate_speech_regions_from_f32_16khz_samples(
+            &model_path,
+            &samples,
+            &regions,
+            "en",
+            false,
+            &WhisperOptions::default(),
+            1,
+        );
+        assert!(!subtitles.is_empty());
+        // Timestamps are mapped back after the leading silence
+        assert!(subtitles[0].start >= 2.0);
+    }
+
+    #[test]
+    fn test_detect_language_from_f32_16khz_samples() {
+        let (audio_path, model_path) = setup();
+
+        let samples: Vec<f32> = hound::WavReader::open(audio_path)
+            .unwrap()
+            .into_samples::<f32>()
+            .map(|x| x.unwrap())
+            .collect();
+
+        let detected = detect_language_from_f32_16khz_samples(
+            &model_path,
+            &samples,
+            30.0,
+            &WhisperOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(detected.code, "en");
+        assert!(detected.probability > 0.5);
+    }
+
+    #[test]
+    fn test_extract_with_options() {
+        let (audio_path, model_path) = setup();
+
+        let samples: Vec<f32> = hound::WavReader::open(audio_path)
+            .unwrap()
+            .into_samples::<f32>()
+            .map(|x| x.unwrap())
+            .collect();
+
+        let options = WhisperOptions {
+            beam_size: 5,
+            n_threads: Some(2),
+            initial_prompt: Some("Ask not what your country can do for you.".to_string()),
+            max_len: 20,
+            ..Default::default()
+        };
+        assert!(matches!(
+            sampling_strategy(&options),
+            SamplingStrategy::BeamSearch { beam_size: 5, .. }
+        ));
+        let state = extract_from_f32_16khz_samples(&model_path, &samples, "en", &options);
+        let subtitles = create_subtitle_from_whisper_state(&state);
+        // The single sentence is split into short segments
+        assert!(subtitles.len() > 1);
+    }
 }
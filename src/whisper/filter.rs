@@ -0,0 +1,275 @@
+use std::fmt;
+
+use crate::utils::{split_words, Subtitle};
+
+/// Phrases whisper is known to produce over silence or music, learned from video outros
+fn known_hallucinations(language: &str) -> &'static [&'static str] {
+    match language {
+        "en" => &[
+            "Thank you for watching",
+            "Thanks for watching",
+            "Please subscribe to my channel",
+            "Subtitles by the Amara.org community",
+        ],
+        "ja" => &[
+            "ご視聴ありがとうございました",
+            "最後までご視聴頂きありがとうございました",
+            "チャンネル登録よろしくお願いします",
+        ],
+        "zh" => &[
+            "请不吝点赞 订阅 转发 打赏支持明镜与点点栏目",
+            "字幕由Amara.org社区提供",
+            "谢谢观看",
+        ],
+        "ko" => &["시청해주셔서 감사합니다", "구독과 좋아요 부탁드립니다"],
+        "fr" => &[
+            "Sous-titres réalisés par la communauté d'Amara.org",
+            "Merci d'avoir regardé",
+        ],
+        "de" => &[
+            "Untertitel der Amara.org-Community",
+            "Vielen Dank fürs Zuschauen",
+        ],
+        "es" => &[
+            "Subtítulos realizados por la comunidad de Amara.org",
+            "Gracias por ver el video",
+        ],
+        _ => &[],
+    }
+}
+
+pub struct FilterOptions {
+    /// Subtitles with exactly this text (case and punctuation aside) are dropped
+    pub hallucinations: Vec<String>,
+    /// Words or phrases repeated more times in a row within a subtitle are cut
+    pub max_repeated_ngrams: usize,
+    /// Consecutive subtitles with the same text are dropped after this many
+    pub max_repeated_subtitles: usize,
+    /// Subtitles with a lower average word probability are dropped, if the words are known
+    pub min_probability: f32,
+}
+
+impl Default for FilterOptions {
+    fn default() -> Self {
+        FilterOptions {
+            hallucinations: Vec::new(),
+            max_repeated_ngrams: 3,
+            max_repeated_subtitles: 2,
+            min_probability: 0.3,
+        }
+    }
+}
+
+impl FilterOptions {
+    /// Default options with the known hallucinations of the language
+    pub fn for_language(language: &str) -> FilterOptions {
+        FilterOptions {
+            hallucinations: known_hallucinations(language)
+                .iter()
+                .map(|phrase| phrase.to_string())
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DropReason {
+    InvalidDuration,
+    Hallucination,
+    LowProbability,
+    Repetition,
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            DropReason::InvalidDuration => "invalid duration",
+            DropReason::Hallucination => "known hallucination",
+            DropReason::LowProbability => "low probability",
+            DropReason::Repetition => "repetition",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// What the filter changed, to be reported to the user
+#[derive(Debug, Default)]
+pub struct FilterReport {
+    pub dropped: Vec<(Subtitle, DropReason)>,
+    /// Subtitles whose repeated words were cut
+    pub shortened: usize,
+}
+
+/// Lowercase letters and digits only, so punctuation and spacing differences still match
+fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// Cut the words or phrases repeated more than `max_repeats` times in a row
+fn cut_repeated_ngrams(text: &str, max_repeats: usize) -> Option<String> {
+    let max_repeats = max_repeats.max(1);
+    let units = split_words(text);
+    let keys: Vec<String> = units
+        .iter()
+        .map(|unit| normalize(&text[unit.clone()]))
+        .collect();
+
+    let mut removed = vec![false; units.len()];
+    let mut i = 0;
+    while i < keys.len() {
+        let mut next = i + 1;
+        // The shortest repeated phrase starting here
+        for n in 1..=(keys.len() - i) / (max_repeats + 1) {
+            let mut count = 1;
+            while i + (count + 1) * n <= keys.len()
+                && keys[i + count * n..i + (count + 1) * n] == keys[i..i + n]
+            {
+                count += 1;
+            }
+            if count > max_repeats {
+                removed[i + max_repeats * n..i + count * n].fill(true);
+                next = i + count * n;
+                break;
+            }
+        }
+        i = next;
+    }
+    if !removed.contains(&true) {
+        return None;
+    }
+
+    let kept: String = units
+        .iter()
+        .zip(removed)
+        .filter(|(_, removed)| !removed)
+        .map(|(unit, _)| &text[unit.clone()])
+        .collect();
+    Some(kept.trim_end().to_string())
+}
+
+/// Remove the hallucinations and repetitions whisper produces, e.g. over silence or music
+pub fn filter_subtitles(
+    subtitles: Vec<Subtitle>,
+    options: &FilterOptions,
+) -> (Vec<Subtitle>, FilterReport) {
+    let hallucinations: Vec<String> = options
+        .hallucinations
+        .iter()
+        .map(|phrase| normalize(phrase))
+        .collect();
+
+    let mut report = FilterReport::default();
+    let mut kept: Vec<Subtitle> = Vec::with_capacity(subtitles.len());
+    let mut repeats = 0;
+    for mut subtitle in subtitles {
+        if subtitle.end <= subtitle.start {
+            report.dropped.push((subtitle, DropReason::InvalidDuration));
+            continue;
+        }
+        if hallucinations.contains(&normalize(&subtitle.text)) {
+            report.dropped.push((subtitle, DropReason::Hallucination));
+            continue;
+        }
+        if !subtitle.words.is_empty() {
+            let probability = subtitle.words.iter().map(|w| w.probability).sum::<f32>()
+                / subtitle.words.len() as f32;
+            if probability < options.min_probability {
+                report.dropped.push((subtitle, DropReason::LowProbability));
+                continue;
+            }
+        }
+        if let Some(text) = cut_repeated_ngrams(&subtitle.text, options.max_repeated_ngrams) {
+            subtitle.text = text;
+            // The timed words do not match the text anymore
+            subtitle.words.clear();
+            report.shortened += 1;
+        }
+
+        let text = normalize(&subtitle.text);
+        match kept.last() {
+            Some(last) if !text.is_empty() && normalize(&last.text) == text => repeats += 1,
+            _ => repeats = 0,
+        }
+        if repeats >= options.max_repeated_subtitles {
+            report.dropped.push((subtitle, DropReason::Repetition));
+            continue;
+        }
+        kept.push(subtitle);
+    }
+    (kept, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Word;
+
+    #[test]
+    fn test_cut_repeated_ngrams() {
+        assert_eq!(cut_repeated_ngrams("Hello, world!", 3), None);
+        assert_eq!(
+            cut_repeated_ngrams("no no no no no way", 3),
+            Some("no no no way".to_string())
+        );
+        assert_eq!(
+            cut_repeated_ngrams("thank you thank you thank you thank you", 1),
+            Some("thank you".to_string())
+        );
+        assert_eq!(
+            cut_repeated_ngrams("ありがとうありがとうありがとう", 1),
+            Some("ありがとう".to_string())
+        );
+        assert_eq!(cut_repeated_ngrams("はは", 3), None);
+    }
+
+    #[test]
+    fn test_filter_subtitles() {
+        let subtitles = vec![
+            Subtitle::new(0.0, 1.0, "Hello.".to_string()),
+            Subtitle::new(1.0, 1.0, "Empty".to_string()),
+            Subtitle::new(2.0, 3.0, "ありがとうございました".to_string()),
+            Subtitle::new(3.0, 4.0, "ありがとうございました。".to_string()),
+            Subtitle::new(4.0, 5.0, "ありがとうございました".to_string()),
+            Subtitle::new(5.0, 6.0, "ありがとうございました".to_string()),
+            Subtitle {
+                words: vec![Word {
+                    start: 6.0,
+                    end: 7.0,
+                    text: "Mumble".to_string(),
+                    probability: 0.1,
+                }],
+                ..Subtitle::new(6.0, 7.0, "Mumble".to_string())
+            },
+            Subtitle::new(7.0, 8.0, "ご視聴ありがとうございました！".to_string()),
+            Subtitle::new(8.0, 9.0, "Bye bye bye bye bye".to_string()),
+        ];
+        let (subtitles, report) = filter_subtitles(subtitles, &FilterOptions::for_language("ja"));
+
+        let texts: Vec<&str> = subtitles.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "Hello.",
+                "ありがとうございました",
+                "ありがとうございました。",
+                "Bye bye bye"
+            ]
+        );
+        let reasons: Vec<DropReason> = report.dropped.iter().map(|(_, r)| *r).collect();
+        assert_eq!(
+            reasons,
+            vec![
+                DropReason::InvalidDuration,
+                DropReason::Repetition,
+                DropReason::Repetition,
+                DropReason::LowProbability,
+                DropReason::Hallucination,
+            ]
+        );
+        assert_eq!(report.shortened, 1);
+    }
+}
@@ -1 +1,3 @@
+pub mod chunked;
 pub mod experiment;
+pub mod filter;